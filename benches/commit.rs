@@ -0,0 +1,55 @@
+//! Compares `RustBox::commit` against redrawing every cell of the frame with `blit`.  Needs a
+//! terminal: run with `cargo bench --bench commit`.
+
+extern crate rustbox;
+
+use std::time::{Duration, Instant};
+
+use rustbox::{Cell, CellBuffer, RustBox, RB_NORMAL};
+
+const FRAMES: u32 = 500;
+
+// A full screen of text in which one row changes from frame to frame, like a clock or a status
+// line over a mostly static view.
+fn frame(width: usize, height: usize, n: u32) -> CellBuffer {
+    let mut buffer = CellBuffer::new(width, height, Cell::default());
+    for y in 0..height {
+        for x in 0..width {
+            let ch = if y == 0 {
+                (b'0' + ((n as usize + x) % 10) as u8) as char
+            } else {
+                (b'a' + ((x + y) % 26) as u8) as char
+            };
+            buffer.set(x, y, Cell::new(ch, RB_NORMAL, RB_NORMAL));
+        }
+    }
+    buffer
+}
+
+fn time<F: FnMut(&mut RustBox, &CellBuffer)>(rb: &mut RustBox, frames: &[CellBuffer],
+                                              mut draw: F) -> Duration {
+    let start = Instant::now();
+    for f in frames {
+        draw(rb, f);
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let mut rb = RustBox::init().unwrap();
+    let (width, height) = (rb.width(), rb.height());
+    let frames: Vec<CellBuffer> = (0..FRAMES).map(|n| frame(width, height, n)).collect();
+
+    let full = time(&mut rb, &frames, |rb, f| {
+        rb.blit(0, 0, f);
+        rb.present();
+    });
+    rb.clear();
+    let diffed = time(&mut rb, &frames, |rb, f| rb.commit(f));
+    drop(rb);
+
+    let per_frame = |d: Duration| d / FRAMES;
+    println!("{}x{}, {} frames", width, height, FRAMES);
+    println!("full redraw: {:?} per frame", per_frame(full));
+    println!("commit:      {:?} per frame", per_frame(diffed));
+}
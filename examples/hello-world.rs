@@ -13,9 +13,9 @@ fn main() {
     rustbox.print(1, 1, rustbox::RB_BOLD, Color::White, Color::Black, "Hello, world!");
     rustbox.print(1, 3, rustbox::RB_BOLD, Color::White, Color::Black,
                   "Press 'q' to quit.");
-    loop {
-        rustbox.present();
-        match rustbox.poll_event() {
+    rustbox.present();
+    for event in rustbox.events() {
+        match event {
             Ok(rustbox::Event::KeyEvent(_, key)) => {
                 match key {
                     Key::Char('q') => { break; }
@@ -0,0 +1,201 @@
+use std::char;
+use std::cmp;
+
+use termbox::RawCell;
+
+use super::{style, Color, OutputMode, Style, RB_NORMAL};
+
+/// The contents of a single cell of the back buffer.
+///
+/// `fg` carries the foreground color along with any attributes (`RB_BOLD` etc.); `bg` carries the
+/// background color.
+///
+/// The default cell is a blank in the terminal's default colors.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Cell {
+    pub ch: char,
+    pub fg: Style,
+    pub bg: Style,
+}
+
+impl Cell {
+    pub fn new(ch: char, fg: Style, bg: Style) -> Cell {
+        Cell { ch, fg, bg }
+    }
+
+    /// A cell showing `ch` with the given attributes and colors, encoded the way `print` would
+    /// draw them in `mode`.
+    ///
+    /// Use this rather than `Style::from(color) | attributes`, which only encodes colors for
+    /// `OutputMode::Normal`: `Cell::styled('x', RB_BOLD, Color::Byte(240), Color::Default,
+    /// OutputMode::Output256)` is bold in palette color 240.
+    pub fn styled(ch: char, sty: Style, fg: Color, bg: Color, mode: OutputMode) -> Cell {
+        let (fg, bg) = style::encode(sty, fg, bg, mode);
+        Cell::new(ch, fg, bg)
+    }
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        Cell::new(' ', RB_NORMAL, RB_NORMAL)
+    }
+}
+
+/// A cell showing `ch` in the terminal's default colors.
+impl From<char> for Cell {
+    fn from(ch: char) -> Cell {
+        Cell::new(ch, RB_NORMAL, RB_NORMAL)
+    }
+}
+
+pub fn from_raw(raw: &RawCell) -> Cell {
+    Cell {
+        ch: char::from_u32(raw.ch).unwrap_or('\u{FFFD}'),
+        fg: Style::from_bits_truncate(raw.fg),
+        bg: Style::from_bits_truncate(raw.bg),
+    }
+}
+
+pub fn to_raw(cell: &Cell) -> RawCell {
+    RawCell {
+        ch: cell.ch as u32,
+        fg: cell.fg.bits(),
+        bg: cell.bg.bits(),
+    }
+}
+
+/// The text of a row of cells, with trailing blanks trimmed.  The blank `print` leaves after each
+/// character `width` measures as two columns wide is skipped.
+pub fn row_text<I: Iterator<Item=char>>(chars: I, width: fn(char) -> usize) -> String {
+    let mut text = String::new();
+    let mut skip = false;
+    for ch in chars {
+        if !skip {
+            text.push(ch);
+        }
+        skip = !skip && width(ch) == 2;
+    }
+    let len = text.trim_end().len();
+    text.truncate(len);
+    text
+}
+
+/// An off-screen grid of cells, drawn independently of the screen and copied onto it with
+/// `RustBox::blit`.
+#[derive(Clone)]
+pub struct CellBuffer {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+}
+
+pub fn buffer_from_raw(width: usize, height: usize, raw: &[RawCell]) -> CellBuffer {
+    CellBuffer {
+        width,
+        height,
+        cells: raw.iter().map(from_raw).collect(),
+    }
+}
+
+/// The cells of `next` that differ from `previous`, as (x, y, cell), clipped to a `width` by
+/// `height` screen.  Every cell is changed if there is no previous frame or either frame doesn't
+/// match the size of the screen.
+pub fn changed_cells(previous: Option<&CellBuffer>, next: &CellBuffer, width: usize,
+                     height: usize) -> Vec<(usize, usize, Cell)> {
+    let previous = previous.filter(|p| {
+        (p.width, p.height) == (next.width, next.height) && (p.width, p.height) == (width, height)
+    });
+    let cols = cmp::min(next.width, width);
+    let rows = cmp::min(next.height, height);
+    let mut changed = Vec::new();
+    for y in 0..rows {
+        for (x, c) in next.row(y)[..cols].iter().enumerate() {
+            if previous.map_or(true, |p| p.row(y)[x] != *c) {
+                changed.push((x, y, *c));
+            }
+        }
+    }
+    changed
+}
+
+impl CellBuffer {
+    /// Create a `width` by `height` buffer with every cell set to `fill`.
+    pub fn new(width: usize, height: usize, fill: Cell) -> CellBuffer {
+        CellBuffer {
+            width,
+            height,
+            cells: vec![fill; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The cell at (x, y), or `None` if it lies outside of the buffer.
+    pub fn get(&self, x: usize, y: usize) -> Option<&Cell> {
+        if x < self.width && y < self.height {
+            Some(&self.cells[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut Cell> {
+        if x < self.width && y < self.height {
+            Some(&mut self.cells[y * self.width + x])
+        } else {
+            None
+        }
+    }
+
+    /// Set the cell at (x, y).  Writes outside of the buffer are ignored.
+    pub fn set(&mut self, x: usize, y: usize, cell: Cell) {
+        if let Some(c) = self.get_mut(x, y) {
+            *c = cell;
+        }
+    }
+
+    /// The cells of row `y`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `y` is not less than `height()`.
+    pub fn row(&self, y: usize) -> &[Cell] {
+        &self.cells[y * self.width..(y + 1) * self.width]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changed_cells, Cell, CellBuffer};
+
+    #[test]
+    fn only_changed_cells_are_drawn() {
+        let before = CellBuffer::new(3, 2, Cell::default());
+        let mut after = before.clone();
+        after.set(1, 0, Cell::from('a'));
+        after.set(2, 1, Cell::from('b'));
+
+        assert_eq!(changed_cells(Some(&before), &after, 3, 2),
+                   vec![(1, 0, Cell::from('a')), (2, 1, Cell::from('b'))]);
+        assert!(changed_cells(Some(&after), &after, 3, 2).is_empty());
+    }
+
+    #[test]
+    fn missing_or_mismatched_baseline_redraws_everything() {
+        let frame = CellBuffer::new(3, 2, Cell::from('x'));
+        // `clear` and resizes drop the committed frame, leaving no baseline.
+        assert_eq!(changed_cells(None, &frame, 3, 2).len(), 6);
+        // A frame of another size is no baseline either.
+        let small = CellBuffer::new(2, 2, Cell::from('x'));
+        assert_eq!(changed_cells(Some(&small), &frame, 3, 2).len(), 6);
+        // A frame matching the buffer but not the screen is redrawn, clipped to the screen.
+        assert_eq!(changed_cells(Some(&frame), &frame, 2, 1), vec![(0, 0, Cell::from('x')),
+                                                                   (1, 0, Cell::from('x'))]);
+    }
+}
@@ -0,0 +1,116 @@
+use std::io;
+use std::cmp;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use termbox::{self, RawEvent};
+
+use super::running;
+use super::{Event, EventError, EventResult, NIL_RAW_EVENT, unpack_event, handle_error,
+            timeout_millis};
+
+// The longest an `EventSource` holds the termbox lock while waiting for input.  Waiting in short
+// slices lets the drawing thread get in between them.
+pub const POLL_SLICE_MS: u64 = 10;
+
+// How long to wait between slices with the lock released.  `Mutex` isn't fair: without a gap, the
+// waiting thread could take the lock straight back before a thread blocked on it gets a turn.
+const POLL_GAP_MS: u64 = 1;
+
+/// Shared ownership of termbox.  The flag is true while termbox is open.
+pub type Termbox = Arc<Mutex<bool>>;
+
+/// Lock termbox.  Hold the guard only for the duration of a single termbox call: the lock is not
+/// reentrant.
+pub fn lock(termbox: &Termbox) -> MutexGuard<bool> {
+    termbox.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Pause between two slices of waiting for input, with the lock released, so that other threads
+/// can draw in between.
+pub fn give_way() {
+    thread::sleep(Duration::from_millis(POLL_GAP_MS));
+}
+
+/// A handle for polling events from another thread, obtained from `RustBox::event_source`.
+///
+/// Every termbox call made by the `RustBox` and its event sources is serialized, so it is safe to
+/// poll events with an `EventSource` on one thread while drawing with the `RustBox` on another.
+/// Events should be read either through event sources or through the `RustBox` itself, not both:
+/// each event goes to whichever happens to be waiting when it arrives, and events read through an
+/// event source don't update `RustBox::size`.
+///
+/// Once the `RustBox` has been dropped, polling returns an error.
+///
+/// ```no_run
+/// use std::thread;
+/// use std::sync::mpsc;
+/// use rustbox::RustBox;
+///
+/// let rb = RustBox::init().unwrap();
+/// let source = rb.event_source();
+/// let (tx, rx) = mpsc::channel();
+/// thread::spawn(move || {
+///     while let Ok(ev) = source.poll_event() {
+///         if tx.send(ev).is_err() {
+///             break;
+///         }
+///     }
+/// });
+/// ```
+#[derive(Clone)]
+pub struct EventSource {
+    termbox: Termbox,
+}
+
+pub fn event_source(termbox: Termbox) -> EventSource {
+    EventSource { termbox }
+}
+
+impl EventSource {
+    /// Block until an event arrives.
+    pub fn poll_event(&self) -> EventResult<Event> {
+        loop {
+            if let Some(ev) = try!(self.peek_slice(POLL_SLICE_MS)) {
+                return Ok(ev);
+            }
+            give_way();
+        }
+    }
+
+    /// Wait up to `timeout` for an event.
+    pub fn peek_event(&self, timeout: Duration) -> EventResult<Option<Event>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            let remaining = if deadline > now { deadline - now } else { Duration::from_millis(0) };
+            let slice = cmp::min(timeout_millis(remaining) as u64, POLL_SLICE_MS);
+            match try!(self.peek_slice(slice)) {
+                Some(ev) => return Ok(Some(ev)),
+                None if slice == 0 || Instant::now() >= deadline => return Ok(None),
+                None => give_way(),
+            }
+        }
+    }
+
+    fn peek_slice(&self, ms: u64) -> EventResult<Option<Event>> {
+        let mut ev = NIL_RAW_EVENT;
+        let ready = {
+            let open = lock(&self.termbox);
+            if !*open || !running::is_initialized() {
+                return Err(EventError::Io(io::Error::new(io::ErrorKind::Other,
+                                                         "RustBox has been closed")));
+            }
+            try!(handle_error(unsafe {
+                termbox::tb_peek_event(&mut ev as *mut RawEvent,
+                                       timeout_millis(Duration::from_millis(ms)))
+            })).is_some()
+        };
+        if ready {
+            unpack_event(ev).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
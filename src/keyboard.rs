@@ -1,3 +1,7 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Key {
     Char(char),
@@ -5,31 +9,313 @@ pub enum Key {
 }
 
 impl Key {
+    /// The key code a terminal sends for Ctrl+`ch`, or `None` if it sends nothing special.
+    ///
+    /// Letters of either case give `CTRL_A` through `CTRL_Z`.  The remaining codes come from
+    /// `CONTROL_KEYS` below; terminals send the same code for several of those characters, so
+    /// e.g. `Key::control('[')` is `Some(key::ESC)`: Ctrl+[ is indistinguishable from Esc.
     pub fn control(ch: char) -> Option<Key> {
-        if ch as u32 > 0xFF {
-            return None
+        match ch {
+            'a'...'z' => Some(Key::Key(ch as u16 - 'a' as u16 + 1)),
+            'A'...'Z' => Some(Key::Key(ch as u16 - 'A' as u16 + 1)),
+            _ => CONTROL_KEYS.iter()
+                .find(|&&(c, _)| c == ch)
+                .map(|&(_, code)| Key::Key(code)),
         }
-        Some(Key::Key(match ch {
-            '2'|'~' => 0,
-            'a'...'z' => ch as u16 - 'a' as u16 + 1,
-            'A'...'Z' => ch as u16 - 'A' as u16 + 1,
-            '3'|'[' => 0x1b,
-            '4'|'\\' => 0x1c,
-            '5'|']' => 0x1d,
-            '6' => 0x1e,
-            '7'|'/'|'_' => 0x1f,
-            '8' => 0x7f,
-            _ => return None,
-        }))
     }
 
-    pub fn funcion(num: u32) -> Option<Key> {
+    /// The function key `F<num>`, for `num` in 1 through 12.
+    pub fn function(num: u32) -> Option<Key> {
         if 1 <= num && num <= 12 {
-            Some(Key::Key(0xFFFF - num as u16 - 1))
+            Some(Key::Key(0xFFFF - (num as u16 - 1)))
         } else {
             None
         }
     }
+
+    /// The character this key types, if any.  termbox reports the space bar as `key::SPACE`
+    /// rather than `Key::Char(' ')`, so both give `Some(' ')`.
+    pub fn as_char(&self) -> Option<char> {
+        match *self {
+            Key::Char(ch) => Some(ch),
+            key::SPACE => Some(' '),
+            _ => None,
+        }
+    }
+
+    /// Whether this key types a character that should be inserted as text, rather than a control
+    /// character or a special key.
+    pub fn is_printable(&self) -> bool {
+        self.as_char().map_or(false, |ch| !ch.is_control())
+    }
+
+    #[deprecated(note = "misspelled and off by one; use `Key::function`")]
+    pub fn funcion(num: u32) -> Option<Key> {
+        Key::function(num)
+    }
+}
+
+// Human-readable names for keys termbox reports as key codes.  Ctrl combinations without a name of
+// their own are written as `C-<char>` instead.
+const KEY_NAMES: &[(Key, &str)] = &[
+    (key::F1, "F1"),
+    (key::F2, "F2"),
+    (key::F3, "F3"),
+    (key::F4, "F4"),
+    (key::F5, "F5"),
+    (key::F6, "F6"),
+    (key::F7, "F7"),
+    (key::F8, "F8"),
+    (key::F9, "F9"),
+    (key::F10, "F10"),
+    (key::F11, "F11"),
+    (key::F12, "F12"),
+    (key::INSERT, "Insert"),
+    (key::DELETE, "Delete"),
+    (key::HOME, "Home"),
+    (key::END, "End"),
+    (key::PGUP, "PageUp"),
+    (key::PGDN, "PageDown"),
+    (key::ARROW_UP, "Up"),
+    (key::ARROW_DOWN, "Down"),
+    (key::ARROW_LEFT, "Left"),
+    (key::ARROW_RIGHT, "Right"),
+    (key::MOUSE_LEFT, "MouseLeft"),
+    (key::MOUSE_RIGHT, "MouseRight"),
+    (key::MOUSE_MIDDLE, "MouseMiddle"),
+    (key::MOUSE_RELEASE, "MouseRelease"),
+    (key::MOUSE_WHEEL_UP, "WheelUp"),
+    (key::MOUSE_WHEEL_DOWN, "WheelDown"),
+    (key::TAB, "Tab"),
+    (key::ENTER, "Enter"),
+    (key::ESC, "Esc"),
+    (key::SPACE, "Space"),
+    (key::BACKSPACE2, "Backspace"),
+];
+
+// The escape sequences (minus the leading ESC) common terminals send for special keys.  Used to
+// reassemble sequences that arrive split up; see `RustBox::set_esc_timeout`.
+const ESCAPE_SEQUENCES: &[(&str, Key)] = &[
+    ("[A", key::ARROW_UP),
+    ("[B", key::ARROW_DOWN),
+    ("[C", key::ARROW_RIGHT),
+    ("[D", key::ARROW_LEFT),
+    ("OA", key::ARROW_UP),
+    ("OB", key::ARROW_DOWN),
+    ("OC", key::ARROW_RIGHT),
+    ("OD", key::ARROW_LEFT),
+    ("[H", key::HOME),
+    ("[F", key::END),
+    ("OH", key::HOME),
+    ("OF", key::END),
+    ("[1~", key::HOME),
+    ("[2~", key::INSERT),
+    ("[3~", key::DELETE),
+    ("[4~", key::END),
+    ("[5~", key::PGUP),
+    ("[6~", key::PGDN),
+    ("OP", key::F1),
+    ("OQ", key::F2),
+    ("OR", key::F3),
+    ("OS", key::F4),
+    ("[11~", key::F1),
+    ("[12~", key::F2),
+    ("[13~", key::F3),
+    ("[14~", key::F4),
+    ("[15~", key::F5),
+    ("[17~", key::F6),
+    ("[18~", key::F7),
+    ("[19~", key::F8),
+    ("[20~", key::F9),
+    ("[21~", key::F10),
+    ("[23~", key::F11),
+    ("[24~", key::F12),
+];
+
+/// How the characters following an ESC compare with the known escape sequences.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SequenceMatch {
+    /// They form the sequence for this key.
+    Key(Key),
+    /// They are the start of a sequence.
+    Prefix,
+    /// They aren't part of any sequence.
+    None,
+}
+
+pub fn match_escape_sequence(seq: &str) -> SequenceMatch {
+    let mut prefix = false;
+    for &(s, k) in ESCAPE_SEQUENCES {
+        if s == seq {
+            return SequenceMatch::Key(k);
+        }
+        prefix = prefix || s.starts_with(seq);
+    }
+    if prefix { SequenceMatch::Prefix } else { SequenceMatch::None }
+}
+
+// The characters other than letters that, combined with Ctrl, produce a key code.  The first
+// character listed for each code is the one `Display` uses.
+const CONTROL_KEYS: &[(char, u16)] = &[
+    ('2', 0x00), ('~', 0x00), ('@', 0x00), (' ', 0x00),
+    ('3', 0x1b), ('[', 0x1b),
+    ('4', 0x1c), ('\\', 0x1c),
+    ('5', 0x1d), (']', 0x1d),
+    ('6', 0x1e), ('^', 0x1e),
+    ('7', 0x1f), ('/', 0x1f), ('_', 0x1f),
+    ('8', 0x7f), ('?', 0x7f),
+];
+
+// The character that, combined with Ctrl, produces the given key code.  This is the inverse of
+// `Key::control`.
+fn control_char(code: u16) -> Option<char> {
+    match code {
+        0x01...0x1a => Some((b'a' + code as u8 - 1) as char),
+        _ => CONTROL_KEYS.iter().find(|&&(_, c)| c == code).map(|&(ch, _)| ch),
+    }
+}
+
+/// The error returned when parsing a `Key` fails.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseKeyError {
+    input: String,
+}
+
+impl fmt::Display for ParseKeyError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}: {:?}", self.description(), self.input)
+    }
+}
+
+impl Error for ParseKeyError {
+    fn description(&self) -> &str {
+        "unrecognized key"
+    }
+}
+
+/// Parses the notation `Display` produces: a single character (`"q"`), a Ctrl combination
+/// (`"C-a"`), a key name (`"F5"`, `"Enter"`, `"Esc"`, `"Up"`, ...; case-insensitive) or a raw
+/// key code (`"U+001B"`).
+impl FromStr for Key {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<Key, ParseKeyError> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => return Ok(Key::Char(ch)),
+            (Some('C'), Some('-')) => {
+                let rest = &s[2..];
+                let mut chars = rest.chars();
+                if let (Some(ch), None) = (chars.next(), chars.next()) {
+                    if let Some(key) = Key::control(ch) {
+                        return Ok(key);
+                    }
+                }
+            },
+            (Some('U'), Some('+')) => {
+                if let Ok(code) = u16::from_str_radix(&s[2..], 16) {
+                    return Ok(Key::Key(code));
+                }
+            },
+            _ => { }
+        }
+        KEY_NAMES.iter()
+            .find(|&&(_, name)| name.eq_ignore_ascii_case(s))
+            .map(|&(key, _)| key)
+            .ok_or(ParseKeyError { input: s.to_string() })
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(&(_, name)) = KEY_NAMES.iter().find(|&&(k, _)| k == *self) {
+            return write!(fmt, "{}", name);
+        }
+        match *self {
+            Key::Char(ch) => write!(fmt, "{}", ch),
+            Key::Key(code) => match control_char(code) {
+                Some(ch) => write!(fmt, "C-{}", ch),
+                None => write!(fmt, "U+{:04X}", code),
+            },
+        }
+    }
+}
+
+/// The `key` module constants, also available as associated constants (`Key::ESC`, ...).
+impl Key {
+    pub const F1: Key = key::F1;
+    pub const F2: Key = key::F2;
+    pub const F3: Key = key::F3;
+    pub const F4: Key = key::F4;
+    pub const F5: Key = key::F5;
+    pub const F6: Key = key::F6;
+    pub const F7: Key = key::F7;
+    pub const F8: Key = key::F8;
+    pub const F9: Key = key::F9;
+    pub const F10: Key = key::F10;
+    pub const F11: Key = key::F11;
+    pub const F12: Key = key::F12;
+    pub const INSERT: Key = key::INSERT;
+    pub const DELETE: Key = key::DELETE;
+    pub const HOME: Key = key::HOME;
+    pub const END: Key = key::END;
+    pub const PGUP: Key = key::PGUP;
+    pub const PGDN: Key = key::PGDN;
+    pub const ARROW_UP: Key = key::ARROW_UP;
+    pub const ARROW_DOWN: Key = key::ARROW_DOWN;
+    pub const ARROW_LEFT: Key = key::ARROW_LEFT;
+    pub const ARROW_RIGHT: Key = key::ARROW_RIGHT;
+    pub const MOUSE_LEFT: Key = key::MOUSE_LEFT;
+    pub const MOUSE_RIGHT: Key = key::MOUSE_RIGHT;
+    pub const MOUSE_MIDDLE: Key = key::MOUSE_MIDDLE;
+    pub const MOUSE_RELEASE: Key = key::MOUSE_RELEASE;
+    pub const MOUSE_WHEEL_UP: Key = key::MOUSE_WHEEL_UP;
+    pub const MOUSE_WHEEL_DOWN: Key = key::MOUSE_WHEEL_DOWN;
+    pub const CTRL_TILDE: Key = key::CTRL_TILDE;
+    pub const CTRL_2: Key = key::CTRL_2;
+    pub const CTRL_A: Key = key::CTRL_A;
+    pub const CTRL_B: Key = key::CTRL_B;
+    pub const CTRL_C: Key = key::CTRL_C;
+    pub const CTRL_D: Key = key::CTRL_D;
+    pub const CTRL_E: Key = key::CTRL_E;
+    pub const CTRL_F: Key = key::CTRL_F;
+    pub const CTRL_G: Key = key::CTRL_G;
+    pub const BACKSPACE: Key = key::BACKSPACE;
+    pub const CTRL_H: Key = key::CTRL_H;
+    pub const TAB: Key = key::TAB;
+    pub const CTRL_I: Key = key::CTRL_I;
+    pub const CTRL_J: Key = key::CTRL_J;
+    pub const CTRL_K: Key = key::CTRL_K;
+    pub const CTRL_L: Key = key::CTRL_L;
+    pub const ENTER: Key = key::ENTER;
+    pub const CTRL_M: Key = key::CTRL_M;
+    pub const CTRL_N: Key = key::CTRL_N;
+    pub const CTRL_O: Key = key::CTRL_O;
+    pub const CTRL_P: Key = key::CTRL_P;
+    pub const CTRL_Q: Key = key::CTRL_Q;
+    pub const CTRL_R: Key = key::CTRL_R;
+    pub const CTRL_S: Key = key::CTRL_S;
+    pub const CTRL_T: Key = key::CTRL_T;
+    pub const CTRL_U: Key = key::CTRL_U;
+    pub const CTRL_V: Key = key::CTRL_V;
+    pub const CTRL_W: Key = key::CTRL_W;
+    pub const CTRL_X: Key = key::CTRL_X;
+    pub const CTRL_Y: Key = key::CTRL_Y;
+    pub const CTRL_Z: Key = key::CTRL_Z;
+    pub const ESC: Key = key::ESC;
+    pub const CTRL_LSQ_BRACKET: Key = key::CTRL_LSQ_BRACKET;
+    pub const CTRL_3: Key = key::CTRL_3;
+    pub const CTRL_4: Key = key::CTRL_4;
+    pub const CTRL_BACKSLASH: Key = key::CTRL_BACKSLASH;
+    pub const CTRL_5: Key = key::CTRL_5;
+    pub const CTRL_RSQ_BRACKET: Key = key::CTRL_RSQ_BRACKET;
+    pub const CTRL_6: Key = key::CTRL_6;
+    pub const CTRL_7: Key = key::CTRL_7;
+    pub const CTRL_SLASH: Key = key::CTRL_SLASH;
+    pub const CTRL_UNDERSCORE: Key = key::CTRL_UNDERSCORE;
+    pub const SPACE: Key = key::SPACE;
+    pub const BACKSPACE2: Key = key::BACKSPACE2;
+    pub const CTRL_8: Key = key::CTRL_8;
 }
 
 pub mod key {
@@ -110,3 +396,90 @@ pub mod key {
     pub const BACKSPACE2: Key = Key::Key(0x7F);
     pub const CTRL_8: Key = Key::Key(0x7F); /* clash with 'DELETE' */
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{key, Key, CONTROL_KEYS};
+
+    #[test]
+    fn control_letters() {
+        assert_eq!(Key::control('a'), Some(key::CTRL_A));
+        assert_eq!(Key::control('A'), Some(key::CTRL_A));
+        assert_eq!(Key::control('z'), Some(key::CTRL_Z));
+        assert_eq!(Key::control('h'), Some(key::BACKSPACE));
+        assert_eq!(Key::control('i'), Some(key::TAB));
+        assert_eq!(Key::control('m'), Some(key::ENTER));
+    }
+
+    #[test]
+    fn control_matches_constants() {
+        let expected = [
+            ('~', key::CTRL_TILDE), ('2', key::CTRL_2),
+            ('[', key::CTRL_LSQ_BRACKET), ('3', key::CTRL_3), ('[', key::ESC),
+            ('4', key::CTRL_4), ('\\', key::CTRL_BACKSLASH),
+            ('5', key::CTRL_5), (']', key::CTRL_RSQ_BRACKET),
+            ('6', key::CTRL_6),
+            ('7', key::CTRL_7), ('/', key::CTRL_SLASH), ('_', key::CTRL_UNDERSCORE),
+            ('8', key::CTRL_8), ('?', key::BACKSPACE2),
+        ];
+        for &(ch, k) in &expected {
+            assert_eq!(Key::control(ch), Some(k), "Ctrl+{:?}", ch);
+        }
+    }
+
+    #[test]
+    fn printable() {
+        assert_eq!(Key::Char('q').as_char(), Some('q'));
+        assert_eq!(key::SPACE.as_char(), Some(' '));
+        assert_eq!(key::ENTER.as_char(), None);
+        assert!(Key::Char('é').is_printable());
+        assert!(key::SPACE.is_printable());
+        assert!(!Key::Char('\t').is_printable());
+        assert!(!key::ESC.is_printable());
+        assert!(!key::ARROW_UP.is_printable());
+    }
+
+    #[test]
+    fn control_unmapped() {
+        assert_eq!(Key::control('1'), None);
+        assert_eq!(Key::control('!'), None);
+        assert_eq!(Key::control('é'), None);
+    }
+
+    #[test]
+    fn parse_notation() {
+        assert_eq!("q".parse(), Ok(Key::Char('q')));
+        assert_eq!("F5".parse(), Ok(key::F5));
+        assert_eq!("Enter".parse(), Ok(key::ENTER));
+        assert_eq!("enter".parse(), Ok(key::ENTER));
+        assert_eq!("Space".parse(), Ok(key::SPACE));
+        assert_eq!("C-a".parse(), Ok(key::CTRL_A));
+        assert_eq!("U+001B".parse(), Ok(key::ESC));
+        assert!("bogus".parse::<Key>().is_err());
+        assert!("C-".parse::<Key>().is_err());
+        assert!("C-ab".parse::<Key>().is_err());
+        assert!("U+XYZ".parse::<Key>().is_err());
+        assert!("".parse::<Key>().is_err());
+    }
+
+    #[test]
+    fn display_notation() {
+        assert_eq!(Key::Char('q').to_string(), "q");
+        assert_eq!(key::F5.to_string(), "F5");
+        assert_eq!(key::ENTER.to_string(), "Enter");
+        assert_eq!(key::SPACE.to_string(), "Space");
+        assert_eq!(key::CTRL_A.to_string(), "C-a");
+        assert_eq!(key::ESC.to_string(), "Esc");
+        assert_eq!(Key::Key(0x1234).to_string(), "U+1234");
+        assert_eq!("U+1234".parse(), Ok(Key::Key(0x1234)));
+    }
+
+    #[test]
+    fn control_round_trips() {
+        let chars = (b'a'..b'z' + 1).map(|b| b as char).chain(CONTROL_KEYS.iter().map(|&(c, _)| c));
+        for ch in chars {
+            let k = Key::control(ch).unwrap();
+            assert_eq!(k.to_string().parse::<Key>(), Ok(k), "Ctrl+{:?}", ch);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use super::{Event, Key, Modifier};
+
+/// A table of key bindings, mapping key presses to actions of type `A`.
+///
+/// ```
+/// # use rustbox::{Event, Key, KeyMap, Modifier};
+/// enum Command { Quit, Save }
+///
+/// let mut keys = KeyMap::new();
+/// keys.insert(None, Key::Char('q'), Command::Quit);
+/// keys.insert(Some(Modifier::Alt), Key::Char('s'), Command::Save);
+///
+/// let ev = Event::KeyEvent(None, Key::Char('q'));
+/// assert!(match keys.dispatch(&ev) { Some(&Command::Quit) => true, _ => false });
+/// ```
+#[derive(Clone, Debug)]
+pub struct KeyMap<A> {
+    bindings: HashMap<(Option<Modifier>, Key), A>,
+}
+
+impl<A> KeyMap<A> {
+    pub fn new() -> KeyMap<A> {
+        KeyMap { bindings: HashMap::new() }
+    }
+
+    /// Bind `key`, pressed with `modifier`, to `action`.  Returns the action it was bound to
+    /// before, if any.
+    pub fn insert(&mut self, modifier: Option<Modifier>, key: Key, action: A) -> Option<A> {
+        self.bindings.insert((modifier, key), action)
+    }
+
+    /// Unbind `key` pressed with `modifier`, returning the action it was bound to.
+    pub fn remove(&mut self, modifier: Option<Modifier>, key: Key) -> Option<A> {
+        self.bindings.remove(&(modifier, key))
+    }
+
+    /// The action `key`, pressed with `modifier`, is bound to.
+    pub fn get(&self, modifier: Option<Modifier>, key: Key) -> Option<&A> {
+        self.bindings.get(&(modifier, key))
+    }
+
+    /// The action bound to the key pressed in `event`.  Events other than key presses have no
+    /// action.
+    pub fn dispatch(&self, event: &Event) -> Option<&A> {
+        match *event {
+            Event::KeyEvent(modifier, key) => self.get(modifier, key),
+            _ => None,
+        }
+    }
+}
+
+impl<A> Default for KeyMap<A> {
+    fn default() -> KeyMap<A> {
+        KeyMap::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::KeyMap;
+    use {key, Event, Key, Modifier};
+
+    #[test]
+    fn dispatch_matches_modifier() {
+        let mut keys = KeyMap::new();
+        keys.insert(None, Key::Char('x'), 1);
+        keys.insert(Some(Modifier::Alt), Key::Char('x'), 2);
+        assert_eq!(keys.dispatch(&Event::KeyEvent(None, Key::Char('x'))), Some(&1));
+        assert_eq!(keys.dispatch(&Event::KeyEvent(Some(Modifier::Alt), Key::Char('x'))), Some(&2));
+        assert_eq!(keys.dispatch(&Event::KeyEvent(Some(Modifier::Ctrl), Key::Char('x'))), None);
+        assert_eq!(keys.dispatch(&Event::KeyEvent(None, key::ENTER)), None);
+        assert_eq!(keys.dispatch(&Event::FocusGained), None);
+    }
+
+    #[test]
+    fn insert_replaces() {
+        let mut keys = KeyMap::new();
+        assert_eq!(keys.insert(None, key::ESC, "cancel"), None);
+        assert_eq!(keys.insert(None, key::ESC, "quit"), Some("cancel"));
+        assert_eq!(keys.get(None, key::ESC), Some(&"quit"));
+        assert_eq!(keys.remove(None, key::ESC), Some("quit"));
+        assert_eq!(keys.get(None, key::ESC), None);
+    }
+}
@@ -0,0 +1,61 @@
+use super::Color;
+
+/// The part a color plays in an application's look, rather than the color itself.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Role {
+    Background,
+    Foreground,
+    /// Headings, highlighted items and the like.
+    Accent,
+    Error,
+    /// The background of selected text or items.
+    Selection,
+}
+
+/// The color for each `Role`, set with `RustBox::set_palette` and drawn with
+/// `RustBox::print_role`.  Swapping palettes (e.g. between a light and a dark theme) recolors
+/// everything drawn by role.
+///
+/// The default palette uses the terminal's default colors for the background and foreground.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Palette {
+    pub background: Color,
+    pub foreground: Color,
+    pub accent: Color,
+    pub error: Color,
+    pub selection: Color,
+}
+
+impl Palette {
+    pub fn color(&self, role: Role) -> Color {
+        match role {
+            Role::Background => self.background,
+            Role::Foreground => self.foreground,
+            Role::Accent => self.accent,
+            Role::Error => self.error,
+            Role::Selection => self.selection,
+        }
+    }
+
+    pub fn set_color(&mut self, role: Role, color: Color) {
+        match role {
+            Role::Background => self.background = color,
+            Role::Foreground => self.foreground = color,
+            Role::Accent => self.accent = color,
+            Role::Error => self.error = color,
+            Role::Selection => self.selection = color,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette {
+            background: Color::Default,
+            foreground: Color::Default,
+            accent: Color::Cyan,
+            error: Color::Red,
+            selection: Color::Blue,
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use keyboard::{self, SequenceMatch};
+use super::{key, Event, EventResult, Key, Modifier};
+
+/// The markers terminals send around pasted text in bracketed paste mode.
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+/// How long to wait for more of a bracketed paste before giving up on its end marker.
+const PASTE_TIMEOUT_MS: u64 = 500;
+
+/// The sequences terminals send when they gain or lose focus, if focus reporting is on.
+const FOCUS_IN: &str = "\x1b[I";
+const FOCUS_OUT: &str = "\x1b[O";
+
+/// Where events are reassembled from: the terminal, through a `RustBox`, or a script of events in
+/// tests.
+pub trait EventQueue {
+    /// Read and decode the next event from the terminal, waiting up to `timeout` for it (forever
+    /// if `None`).  `None` means the timeout expired.  Events in `pending` are not returned.
+    fn read(&mut self, timeout: Option<Duration>) -> EventResult<Option<Event>>;
+
+    /// Events that have been read ahead, to be returned before anything read from the terminal.
+    fn pending(&mut self) -> &mut VecDeque<Event>;
+}
+
+/// Which events to reassemble; see `RustBox::set_esc_timeout`, `InitOptions::normalize_keys`,
+/// `RustBox::set_bracketed_paste` and `RustBox::set_focus_reporting`.
+#[derive(Clone, Copy, Default)]
+pub struct Settings {
+    pub esc_timeout: Option<Duration>,
+    pub normalize_keys: bool,
+    pub bracketed_paste: bool,
+    pub focus_reporting: bool,
+}
+
+/// Turn an event just read from the terminal into the event to report, reading ahead for the rest
+/// of a burst of resizes or an escape sequence the event begins.
+pub fn reassemble<Q: EventQueue>(queue: &mut Q, settings: Settings, ev: Event)
+                                 -> EventResult<Event> {
+    let ev = try!(coalesce_resize(queue, ev));
+    let ev = try!(reassemble_escape(queue, settings, ev));
+    reassemble_report(queue, settings, ev)
+}
+
+/// The text a key event stands for, as the terminal sent it: Alt is an ESC prefix and Ctrl
+/// combinations are control characters.  Other events, and special keys such as the arrows, have
+/// none.
+fn event_text(ev: &Event) -> Option<String> {
+    let (m, key) = match *ev {
+        Event::KeyEvent(m, key) => (m, key),
+        _ => return None,
+    };
+    let ch = match (m, key) {
+        // Replayed or deserialized events may carry Ctrl with characters that have no control
+        // code; those have no text.
+        (Some(Modifier::Ctrl), Key::Char(ch)) => match Key::control(ch) {
+            Some(Key::Key(code)) if code < 0x80 => code as u8 as char,
+            _ => return None,
+        },
+        (_, Key::Char(ch)) => ch,
+        (_, Key::Key(code)) if code < 0x80 => code as u8 as char,
+        _ => return None,
+    };
+    let mut text = String::new();
+    if m == Some(Modifier::Alt) {
+        text.push('\x1b');
+    }
+    text.push(ch);
+    Some(text)
+}
+
+// If `ev` is a resize, skip any further resizes that are already waiting, so that a burst of
+// them (e.g. while the window is dragged) is reported as one event with the final size.
+fn coalesce_resize<Q: EventQueue>(queue: &mut Q, ev: Event) -> EventResult<Event> {
+    let mut ev = ev;
+    if let Event::ResizeEvent { .. } = ev {
+        while let Some(next) = try!(queue.read(Some(Duration::from_millis(0)))) {
+            if let Event::ResizeEvent { .. } = next {
+                ev = next;
+            } else {
+                queue.pending().push_back(next);
+                break;
+            }
+        }
+    }
+    Ok(ev)
+}
+
+// If `ev` is a bare ESC, try to read the rest of an escape sequence after it.  Events read
+// that don't complete a sequence are queued to be returned after the ESC.
+//
+// With `normalize_keys`, this also happens without an ESC timeout (the sequence is expected
+// to have arrived all at once), and for Alt+[ and Alt+O, which is how termbox reports the
+// start of an unknown sequence in `InputMode::Alt`.
+fn reassemble_escape<Q: EventQueue>(queue: &mut Q, settings: Settings, ev: Event)
+                                    -> EventResult<Event> {
+    let mut seq = match ev {
+        Event::KeyEvent(None, key::ESC) => String::new(),
+        Event::KeyEvent(Some(Modifier::Alt), Key::Char(ch)) if ch == '[' || ch == 'O' => {
+            ch.to_string()
+        },
+        _ => return Ok(ev),
+    };
+    let timeout = match settings.esc_timeout {
+        Some(timeout) if seq.is_empty() || settings.normalize_keys => timeout,
+        None if settings.normalize_keys => Duration::from_millis(0),
+        _ => return Ok(ev),
+    };
+    let mut read = Vec::new();
+    while let Some(next) = try!(queue.read(Some(timeout))) {
+        let ch = match next {
+            Event::KeyEvent(None, Key::Char(ch)) => Some(ch),
+            _ => None,
+        };
+        read.push(next);
+        if let Some(ch) = ch {
+            seq.push(ch);
+            match keyboard::match_escape_sequence(&seq) {
+                SequenceMatch::Key(k) => return Ok(Event::KeyEvent(None, k)),
+                SequenceMatch::Prefix => continue,
+                SequenceMatch::None => { },
+            }
+        }
+        break;
+    }
+    queue.pending().extend(read);
+    Ok(ev)
+}
+
+// If `ev` begins one of the sequences the terminal reports pastes or focus changes with (in
+// the modes that are enabled), read the rest of it.  Events read that don't complete a
+// sequence are put back to be returned after `ev`.
+fn reassemble_report<Q: EventQueue>(queue: &mut Q, settings: Settings, ev: Event)
+                                    -> EventResult<Event> {
+    let mut seq = match event_text(&ev) {
+        Some(text) => text,
+        None => return Ok(ev),
+    };
+    let mut reports = Vec::new();
+    if settings.bracketed_paste {
+        reports.push(PASTE_START);
+    }
+    if settings.focus_reporting {
+        reports.extend_from_slice(&[FOCUS_IN, FOCUS_OUT]);
+    }
+    if reports.is_empty() || !seq.starts_with('\x1b') {
+        return Ok(ev);
+    }
+    // The terminal sends each sequence all at once, so there's no need to wait for the rest.
+    let mut read = Vec::new();
+    while !reports.contains(&seq.as_str()) && reports.iter().any(|r| r.starts_with(&*seq)) {
+        let next = match try!(next_event(queue, Some(Duration::from_millis(0)))) {
+            Some(next) => next,
+            None => break,
+        };
+        let text = event_text(&next);
+        read.push(next);
+        match text {
+            Some(text) => seq.push_str(&text),
+            None => break,
+        }
+    }
+    match &*seq {
+        PASTE_START if settings.bracketed_paste => read_paste(queue),
+        FOCUS_IN if settings.focus_reporting => Ok(Event::FocusGained),
+        FOCUS_OUT if settings.focus_reporting => Ok(Event::FocusLost),
+        _ => {
+            for ev in read.into_iter().rev() {
+                queue.pending().push_front(ev);
+            }
+            Ok(ev)
+        },
+    }
+}
+
+// Read pasted text up to the marker that ends a bracketed paste.  Events other than text (e.g.
+// resizes) that arrive during the paste are returned after it.  The terminal sends a paste in
+// one burst, so if nothing arrives for `PASTE_TIMEOUT_MS` the end marker has been lost and
+// the text read so far is returned, rather than blocking until the next key press.
+fn read_paste<Q: EventQueue>(queue: &mut Q) -> EventResult<Event> {
+    let mut text = String::new();
+    let mut others = Vec::new();
+    let timeout = Duration::from_millis(PASTE_TIMEOUT_MS);
+    while let Some(next) = try!(next_event(queue, Some(timeout))) {
+        match event_text(&next) {
+            Some(t) => text.push_str(&t),
+            None => others.push(next),
+        }
+        if text.ends_with(PASTE_END) {
+            let len = text.len() - PASTE_END.len();
+            text.truncate(len);
+            break;
+        }
+    }
+    for ev in others.into_iter().rev() {
+        queue.pending().push_front(ev);
+    }
+    Ok(Event::Paste(text.replace("\r\n", "\n").replace('\r', "\n")))
+}
+
+// The next event to reassemble: the first in `pending`, or else one read within `timeout`
+// (blocking if it is `None`).
+fn next_event<Q: EventQueue>(queue: &mut Q, timeout: Option<Duration>)
+                             -> EventResult<Option<Event>> {
+    if let Some(ev) = queue.pending().pop_front() {
+        return Ok(Some(ev));
+    }
+    queue.read(timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    use super::{reassemble, EventQueue, Settings, PASTE_TIMEOUT_MS};
+    use {key, Event, EventResult, Key, Modifier};
+
+    // Events as they arrive from the terminal.  `None` stands for a read timing out; once the
+    // script runs out, every read times out.  The timeout of each read is logged.
+    struct Script {
+        script: VecDeque<Option<Event>>,
+        pending: VecDeque<Event>,
+        timeouts: Vec<Option<Duration>>,
+    }
+
+    impl Script {
+        fn new(script: Vec<Option<Event>>) -> Script {
+            Script { script: script.into_iter().collect(), pending: VecDeque::new(),
+                     timeouts: Vec::new() }
+        }
+
+        // Reassemble `first` as if it had just been read, and then everything left over.
+        fn run(&mut self, settings: Settings, first: Event) -> Vec<Event> {
+            let mut events = vec![reassemble(self, settings, first).unwrap()];
+            loop {
+                let next = match self.pending.pop_front() {
+                    Some(ev) => ev,
+                    None => match self.script.pop_front() {
+                        Some(Some(ev)) => reassemble(self, settings, ev).unwrap(),
+                        Some(None) => continue,
+                        None => return events,
+                    },
+                };
+                events.push(next);
+            }
+        }
+    }
+
+    impl EventQueue for Script {
+        fn read(&mut self, timeout: Option<Duration>) -> EventResult<Option<Event>> {
+            self.timeouts.push(timeout);
+            Ok(self.script.pop_front().and_then(|ev| ev))
+        }
+
+        fn pending(&mut self) -> &mut VecDeque<Event> {
+            &mut self.pending
+        }
+    }
+
+    // termbox reports control characters (and space) as key codes.
+    fn ch(c: char) -> Option<Event> {
+        Some(Event::KeyEvent(None, if c <= ' ' || c == '\x7f' {
+            Key::Key(c as u16)
+        } else {
+            Key::Char(c)
+        }))
+    }
+
+    fn chars(s: &str) -> Vec<Option<Event>> {
+        s.chars().map(ch).collect()
+    }
+
+    fn resize(width: usize, height: usize) -> Event {
+        Event::ResizeEvent { width, height }
+    }
+
+    const ESC: Event = Event::KeyEvent(None, key::ESC);
+
+    fn esc_timeout() -> Settings {
+        Settings { esc_timeout: Some(Duration::from_millis(50)), ..Settings::default() }
+    }
+
+    #[test]
+    fn resize_bursts_are_coalesced() {
+        let mut script = Script::new(vec![Some(resize(90, 30)), Some(resize(100, 40)), ch('q'),
+                                          Some(resize(80, 24))]);
+        assert_eq!(script.run(Settings::default(), resize(85, 25)),
+                   vec![resize(100, 40), Event::KeyEvent(None, Key::Char('q')), resize(80, 24)]);
+        // Only resizes already waiting are skipped.
+        assert_eq!(script.timeouts[0], Some(Duration::from_millis(0)));
+    }
+
+    #[test]
+    fn split_escape_sequences_are_reassembled() {
+        let mut script = Script::new(chars("[Ax"));
+        assert_eq!(script.run(esc_timeout(), ESC),
+                   vec![Event::KeyEvent(None, key::ARROW_UP),
+                        Event::KeyEvent(None, Key::Char('x'))]);
+        assert_eq!(script.timeouts[0], Some(Duration::from_millis(50)));
+
+        // Without a timeout, ESC is reported as it is.
+        let mut script = Script::new(chars("[A"));
+        assert_eq!(script.run(Settings::default(), ESC)[0], ESC);
+    }
+
+    #[test]
+    fn escape_timeout_flushes_partial_sequences() {
+        let mut script = Script::new(vec![ch('['), None, ch('A')]);
+        assert_eq!(script.run(esc_timeout(), ESC),
+                   vec![ESC, Event::KeyEvent(None, Key::Char('[')),
+                        Event::KeyEvent(None, Key::Char('A'))]);
+
+        // Characters that can't continue a sequence are returned after the ESC.
+        let mut script = Script::new(chars("q"));
+        assert_eq!(script.run(esc_timeout(), ESC),
+                   vec![ESC, Event::KeyEvent(None, Key::Char('q'))]);
+    }
+
+    #[test]
+    fn normalized_keys_reassemble_alt_prefixes() {
+        let settings = Settings { normalize_keys: true, ..Settings::default() };
+        let mut script = Script::new(chars("B"));
+        assert_eq!(script.run(settings, Event::KeyEvent(Some(Modifier::Alt), Key::Char('['))),
+                   vec![Event::KeyEvent(None, key::ARROW_DOWN)]);
+        assert_eq!(script.timeouts[0], Some(Duration::from_millis(0)));
+    }
+
+    #[test]
+    fn bracketed_paste_is_read_as_one_event() {
+        let settings = Settings { bracketed_paste: true, ..Settings::default() };
+        let mut events = chars("[200~a b\r");
+        events.push(Some(resize(80, 24)));
+        events.extend(chars("c\x1b[201~x"));
+        let mut script = Script::new(events);
+        assert_eq!(script.run(settings, ESC),
+                   vec![Event::Paste(String::from("a b\nc")), resize(80, 24),
+                        Event::KeyEvent(None, Key::Char('x'))]);
+    }
+
+    #[test]
+    fn paste_without_end_marker_times_out() {
+        let settings = Settings { bracketed_paste: true, ..Settings::default() };
+        let mut script = Script::new(chars("[200~abc"));
+        assert_eq!(script.run(settings, ESC), vec![Event::Paste(String::from("abc"))]);
+        assert_eq!(script.timeouts.last(), Some(&Some(Duration::from_millis(PASTE_TIMEOUT_MS))));
+    }
+
+    #[test]
+    fn focus_reports_are_decoded() {
+        let settings = Settings { focus_reporting: true, ..Settings::default() };
+        let mut script = Script::new(chars("[I\x1b[Oq"));
+        assert_eq!(script.run(settings, ESC),
+                   vec![Event::FocusGained, Event::FocusLost,
+                        Event::KeyEvent(None, Key::Char('q'))]);
+
+        // Without focus reporting, the sequence is left as keys.
+        let mut script = Script::new(chars("[I"));
+        assert_eq!(script.run(Settings::default(), ESC),
+                   vec![ESC, Event::KeyEvent(None, Key::Char('[')),
+                        Event::KeyEvent(None, Key::Char('I'))]);
+    }
+
+    #[test]
+    fn reports_in_alt_mode() {
+        // In `InputMode::Alt`, termbox reports the ESC as Alt on the next character.
+        let settings = Settings { focus_reporting: true, ..Settings::default() };
+        let mut script = Script::new(chars("O"));
+        assert_eq!(script.run(settings, Event::KeyEvent(Some(Modifier::Alt), Key::Char('['))),
+                   vec![Event::FocusLost]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::char;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use super::{Event, Key, Modifier};
+
+// Events are logged one per line:
+//
+//     key - q
+//     key alt Left
+//     key ctrl c
+//     resize 80 24
+//     mouse 10 3 MouseLeft
+//     drag 11 3 MouseLeft
+//     move 12 4
+//     paste hello#20;world#A;
+//     focus in
+//
+// Keys use the notation of `Key`'s `Display` impl, except that whitespace and control characters
+// are written as `#` followed by their hex code point so that every key is a single word.  Pasted
+// text escapes the same characters, and `#`, as `#` followed by the hex code point and `;`.
+
+/// Writes events to an event log as they are read.
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Recorder> {
+        Ok(Recorder { file: try!(File::create(path)) })
+    }
+
+    /// Append an event to the log.  Each event is written out immediately, so the log is complete
+    /// even if the program crashes.
+    pub fn record(&mut self, ev: &Event) -> io::Result<()> {
+        writeln!(self.file, "{}", encode(ev))
+    }
+}
+
+fn encode_key(key: Key) -> String {
+    match key {
+        Key::Char(ch) if ch.is_whitespace() || ch.is_control() => format!("#{:X}", ch as u32),
+        key => key.to_string(),
+    }
+}
+
+fn decode_key(s: &str) -> Option<Key> {
+    if s.len() > 1 && s.starts_with('#') {
+        return u32::from_str_radix(&s[1..], 16).ok().and_then(char::from_u32).map(Key::Char);
+    }
+    s.parse().ok()
+}
+
+/// Escape pasted text as a single word, with whitespace, control characters and `#` as `#HEX;`.
+fn encode_text(text: &str) -> String {
+    let mut word = String::new();
+    for ch in text.chars() {
+        if ch.is_whitespace() || ch.is_control() || ch == '#' {
+            word.push_str(&format!("#{:X};", ch as u32));
+        } else {
+            word.push(ch);
+        }
+    }
+    word
+}
+
+fn decode_text(word: &str) -> Option<String> {
+    let mut text = String::new();
+    let mut rest = word;
+    while let Some(i) = rest.find('#') {
+        text.push_str(&rest[..i]);
+        let end = match rest[i..].find(';') {
+            Some(end) => i + end,
+            None => return None,
+        };
+        let ch = u32::from_str_radix(&rest[i + 1..end], 16).ok().and_then(char::from_u32);
+        text.push(match ch {
+            Some(ch) => ch,
+            None => return None,
+        });
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+    Some(text)
+}
+
+/// Encode an event as a line of an event log (without the trailing newline).
+pub fn encode(ev: &Event) -> String {
+    match *ev {
+        Event::KeyEvent(m, key) => {
+            let m = match m {
+                None => "-",
+                Some(Modifier::Alt) => "alt",
+                Some(Modifier::Ctrl) => "ctrl",
+            };
+            format!("key {} {}", m, encode_key(key))
+        },
+        Event::ResizeEvent { width, height } => format!("resize {} {}", width, height),
+        Event::MouseEvent { x, y, key } => format!("mouse {} {} {}", x, y, encode_key(key)),
+        Event::MouseDrag { x, y, key } => format!("drag {} {} {}", x, y, encode_key(key)),
+        Event::MouseMove { x, y } => format!("move {} {}", x, y),
+        Event::Paste(ref text) => format!("paste {}", encode_text(text)),
+        Event::FocusGained => "focus in".to_string(),
+        Event::FocusLost => "focus out".to_string(),
+    }
+}
+
+/// Decode a line of an event log, or `None` if it isn't a valid event.
+pub fn decode(line: &str) -> Option<Event> {
+    let words: Vec<&str> = line.split(' ').collect();
+    match (words[0], words.len()) {
+        ("key", 3) => {
+            let m = match words[1] {
+                "-" => None,
+                "alt" => Some(Modifier::Alt),
+                "ctrl" => Some(Modifier::Ctrl),
+                _ => return None,
+            };
+            decode_key(words[2]).map(|key| Event::KeyEvent(m, key))
+        },
+        ("resize", 3) => match (words[1].parse(), words[2].parse()) {
+            (Ok(width), Ok(height)) => Some(Event::ResizeEvent { width, height }),
+            _ => None,
+        },
+        ("mouse", 4) => match (words[1].parse(), words[2].parse(), decode_key(words[3])) {
+            (Ok(x), Ok(y), Some(key)) => Some(Event::MouseEvent { x, y, key }),
+            _ => None,
+        },
+        ("drag", 4) => match (words[1].parse(), words[2].parse(), decode_key(words[3])) {
+            (Ok(x), Ok(y), Some(key)) => Some(Event::MouseDrag { x, y, key }),
+            _ => None,
+        },
+        ("move", 3) => match (words[1].parse(), words[2].parse()) {
+            (Ok(x), Ok(y)) => Some(Event::MouseMove { x, y }),
+            _ => None,
+        },
+        ("focus", 2) => match words[1] {
+            "in" => Some(Event::FocusGained),
+            "out" => Some(Event::FocusLost),
+            _ => None,
+        },
+        ("paste", 1) => Some(Event::Paste(String::new())),
+        ("paste", 2) => decode_text(words[1]).map(Event::Paste),
+        _ => None,
+    }
+}
+
+/// Read an event log written by `RustBox::record_events`.  Blank lines are skipped.
+pub fn read_event_log<P: AsRef<Path>>(path: P) -> io::Result<Vec<Event>> {
+    let file = BufReader::new(try!(File::open(path)));
+    let mut events = Vec::new();
+    for (i, line) in file.lines().enumerate() {
+        let line = try!(line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        match decode(line.trim()) {
+            Some(ev) => events.push(ev),
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              format!("invalid event on line {}: {:?}",
+                                                      i + 1, line))),
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode};
+    use {key, Event, Key, Modifier};
+
+    #[test]
+    fn events_round_trip() {
+        let events = [
+            Event::KeyEvent(None, Key::Char('q')),
+            Event::KeyEvent(Some(Modifier::Alt), Key::Char('x')),
+            Event::KeyEvent(Some(Modifier::Ctrl), Key::Char('a')),
+            Event::KeyEvent(Some(Modifier::Alt), key::ARROW_LEFT),
+            Event::KeyEvent(None, Key::Char(' ')),
+            Event::KeyEvent(None, Key::Char('#')),
+            Event::KeyEvent(None, Key::Char('\t')),
+            Event::KeyEvent(None, key::BACKSPACE),
+            Event::KeyEvent(None, key::BACKSPACE2),
+            Event::KeyEvent(None, key::SPACE),
+            Event::ResizeEvent { width: 80, height: 24 },
+            Event::MouseEvent { x: 3, y: 7, key: key::MOUSE_LEFT },
+            Event::MouseEvent { x: 0, y: 0, key: key::MOUSE_WHEEL_DOWN },
+            Event::MouseDrag { x: 4, y: 7, key: key::MOUSE_LEFT },
+            Event::MouseMove { x: 5, y: 8 },
+            Event::Paste(String::from("a #tag\nand more")),
+            Event::Paste(String::new()),
+            Event::FocusGained,
+            Event::FocusLost,
+        ];
+        for ev in &events {
+            let line = encode(ev);
+            assert!(!line.contains('\n'), "{:?}", line);
+            assert_eq!(decode(&line).as_ref(), Some(ev), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn control_keys_are_distinct() {
+        assert_eq!(encode(&Event::KeyEvent(None, key::BACKSPACE)), "key - C-h");
+        assert_eq!(encode(&Event::KeyEvent(None, key::BACKSPACE2)), "key - Backspace");
+        assert_eq!(encode(&Event::KeyEvent(None, Key::Char(' '))), "key - #20");
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        let lines = ["", "key", "key - ", "key shift q", "key - bogus", "resize 80",
+                     "resize 80 tall", "mouse 1 2", "mouse 1 2 q q", "focus sideways",
+                     "paste #zz;", "paste #41", "jump 1 2"];
+        for line in &lines {
+            assert_eq!(decode(line), None, "{:?}", line);
+        }
+    }
+}
@@ -1,39 +1,201 @@
-#![feature(std_misc)]
-
 extern crate libc;
 extern crate termbox_sys as termbox;
+extern crate unicode_segmentation;
+extern crate unicode_width;
 #[macro_use] extern crate bitflags;
+#[cfg(feature = "serde")] extern crate serde;
 
 pub use self::running::running;
-pub use self::style::{Style, RB_BOLD, RB_UNDERLINE, RB_REVERSE, RB_NORMAL};
+pub use self::style::{Style, RB_BOLD, RB_UNDERLINE, RB_REVERSE, RB_BLINK, RB_NORMAL};
+pub use self::input_mode::InputModes;
 
 use std::error::Error;
-use std::io;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
 use std::fmt;
 use std::char;
-use std::time::duration::Duration;
+use std::iter;
+use std::cmp;
+use std::collections::VecDeque;
+use std::env;
+use std::panic;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::convert::From;
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use termbox::RawEvent;
+use termbox::RawCell;
 use libc::c_int;
 
+mod cell;
+mod event_source;
 mod keyboard;
+mod keymap;
+mod palette;
+mod reassemble;
+mod record;
+#[cfg(feature = "serde")]
+mod serialize;
+mod stderr;
+mod surface;
+mod text;
+mod tty;
+pub mod widgets;
 
-pub use keyboard::Key;
+pub use cell::{Cell, CellBuffer};
+pub use event_source::EventSource;
+pub use keyboard::{Key, ParseKeyError};
 pub use keyboard::key;
+pub use keymap::KeyMap;
+pub use palette::{Palette, Role};
+pub use record::read_event_log;
+pub use surface::Surface;
+#[cfg(feature = "test-backend")]
+pub use surface::TestBackend;
+pub use text::{text_width, grapheme_count, nth_grapheme_column, PreparedText};
+
+use reassemble::EventQueue;
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Modifier {
+    /// Only reported in `InputMode::Alt`, where termbox treats an ESC prefix as Alt.
     Alt,
+    /// Only reported when enabled with `RustBox::set_decode_ctrl`.
+    Ctrl,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, PartialEq, Eq, Debug)]
 pub enum Event {
+    /// A key press.  The modifier applies to any kind of key: Alt+Left arrives as
+    /// `KeyEvent(Some(Modifier::Alt), key::ARROW_LEFT)` just as Alt+x arrives as
+    /// `KeyEvent(Some(Modifier::Alt), Key::Char('x'))`.
     KeyEvent(Option<Modifier>, Key),
-    ResizeEvent(i32, i32),
+    /// The terminal was resized to `width` by `height` cells.  When read through a `RustBox`,
+    /// this always matches its `size()` afterwards, and resizes that are waiting to be read
+    /// together are reported as one.
+    ResizeEvent { width: usize, height: usize },
+    /// A mouse button pressed or released, or the wheel turned, at the given cell.  `key` is one
+    /// of the `key::MOUSE_*` constants; a release is reported as `key::MOUSE_RELEASE`, without
+    /// saying which button.
+    MouseEvent { x: i32, y: i32, key: Key },
+    /// The mouse moved to the given cell with a button held.  `key` is the button's
+    /// `key::MOUSE_*` constant.  Reported in `InputMode::Mouse`.
+    MouseDrag { x: i32, y: i32, key: Key },
+    /// The mouse moved to the given cell with no button held.  Only reported in
+    /// `InputMode::MouseMotion`.
+    MouseMove { x: i32, y: i32 },
+    /// Text pasted into the terminal, reported in one piece when enabled with
+    /// `RustBox::set_bracketed_paste`.  Line breaks are normalized to `\n`.
+    Paste(String),
+    /// The terminal gained focus.  Only reported when enabled with
+    /// `RustBox::set_focus_reporting`.
+    FocusGained,
+    /// The terminal lost focus.
+    FocusLost,
 }
 
-#[derive(Clone, Copy, Debug)]
+impl Event {
+    /// Whether this is a press of the left mouse button.
+    pub fn is_left_click(&self) -> bool {
+        self.is_mouse(key::MOUSE_LEFT)
+    }
+
+    /// Whether this is a press of the right mouse button.
+    pub fn is_right_click(&self) -> bool {
+        self.is_mouse(key::MOUSE_RIGHT)
+    }
+
+    /// Whether this is a press of the middle mouse button.
+    pub fn is_middle_click(&self) -> bool {
+        self.is_mouse(key::MOUSE_MIDDLE)
+    }
+
+    /// Whether this is a release of a mouse button.
+    pub fn is_mouse_release(&self) -> bool {
+        self.is_mouse(key::MOUSE_RELEASE)
+    }
+
+    /// Whether the mouse wheel was turned up (away from the user).
+    ///
+    /// ```
+    /// # use rustbox::{Event, key};
+    /// let ev = Event::MouseEvent { x: 3, y: 7, key: key::MOUSE_WHEEL_UP };
+    /// assert!(ev.is_scroll_up());
+    /// assert!(!ev.is_scroll_down());
+    /// ```
+    pub fn is_scroll_up(&self) -> bool {
+        self.is_mouse(key::MOUSE_WHEEL_UP)
+    }
+
+    /// Whether the mouse wheel was turned down (towards the user).
+    pub fn is_scroll_down(&self) -> bool {
+        self.is_mouse(key::MOUSE_WHEEL_DOWN)
+    }
+
+    /// The cell a mouse event (including a drag or move) happened at.
+    pub fn mouse_position(&self) -> Option<(i32, i32)> {
+        match *self {
+            Event::MouseEvent { x, y, .. } |
+            Event::MouseDrag { x, y, .. } |
+            Event::MouseMove { x, y } => Some((x, y)),
+            _ => None,
+        }
+    }
+
+    fn is_mouse(&self, button: Key) -> bool {
+        match *self {
+            Event::MouseEvent { key, .. } => key == button,
+            _ => false,
+        }
+    }
+}
+
+/// What `RustBox::run_loop` passes to its handler.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LoopEvent {
+    /// An event was read.
+    Input(Event),
+    /// The tick interval has passed.
+    Tick,
+}
+
+/// Whether `RustBox::run_loop` should keep going.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ControlFlow {
+    Continue,
+    Break,
+}
+
+/// The line style used by `RustBox::draw_border`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BorderStyle {
+    /// `┌─┐`
+    Single,
+    /// `╔═╗`
+    Double,
+    /// `╭─╮`
+    Rounded,
+    /// `+-+`
+    Ascii,
+}
+
+impl BorderStyle {
+    // (top-left, top-right, bottom-left, bottom-right, horizontal, vertical)
+    fn chars(self) -> (char, char, char, char, char, char) {
+        match self {
+            BorderStyle::Single => ('┌', '┐', '└', '┘', '─', '│'),
+            BorderStyle::Double => ('╔', '╗', '╚', '╝', '═', '║'),
+            BorderStyle::Rounded => ('╭', '╮', '╰', '╯', '─', '│'),
+            BorderStyle::Ascii => ('+', '+', '+', '+', '-', '|'),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum InputMode {
     /// When ESC sequence is in the buffer and it doesn't match any known
     /// ESC sequence => ESC means TB_KEY_ESC
@@ -41,73 +203,649 @@ pub enum InputMode {
     /// When ESC sequence is in the buffer and it doesn't match any known
     /// sequence => ESC enables TB_MOD_ALT modifier for the next keyboard event.
     Alt     = 0x02,
+    /// Report mouse clicks as `Event::MouseEvent`.  Combine with `Esc` or `Alt`, e.g.
+    /// `InputMode::Esc | InputMode::Mouse`.
+    Mouse   = 0x04,
+    /// Like `Mouse`, but also report the mouse moving with no button held, as
+    /// `Event::MouseMove`.  termbox doesn't know about this mode; rustbox enables it itself.
+    MouseMotion = 0x08,
+}
+
+mod input_mode {
+    use std::ops::BitOr;
+    use libc::c_int;
+    use super::InputMode;
+
+    bitflags! {
+        flags InputModes: c_int {
+            const TB_INPUT_ESC = 0x01,
+            const TB_INPUT_ALT = 0x02,
+            const TB_INPUT_MOUSE = 0x04,
+            // termbox keeps bits it doesn't know about, so `get_input_mode` reports this too.
+            const RB_INPUT_MOUSE_MOTION = 0x08,
+        }
+    }
+
+    impl From<InputMode> for InputModes {
+        fn from(mode: InputMode) -> InputModes {
+            match mode {
+                InputMode::Esc => TB_INPUT_ESC,
+                InputMode::Alt => TB_INPUT_ALT,
+                InputMode::Mouse => TB_INPUT_MOUSE,
+                InputMode::MouseMotion => TB_INPUT_MOUSE | RB_INPUT_MOUSE_MOTION,
+            }
+        }
+    }
+
+    impl BitOr for InputMode {
+        type Output = InputModes;
+
+        fn bitor(self, other: InputMode) -> InputModes {
+            InputModes::from(self) | InputModes::from(other)
+        }
+    }
+
+    impl BitOr<InputMode> for InputModes {
+        type Output = InputModes;
+
+        fn bitor(self, other: InputMode) -> InputModes {
+            self | InputModes::from(other)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OutputMode {
+    /// Leave the output mode unchanged.
+    Current     = 0x00,
+    /// The 8 basic colors (plus the terminal default).
+    Normal      = 0x01,
+    /// The full xterm-256 palette:
+    ///     0x00 - 0x07: the 8 basic colors
+    ///     0x08 - 0x0f: the bright variants of the basic colors
+    ///     0x10 - 0xe7: the 6x6x6 color cube
+    ///     0xe8 - 0xff: 24 shades of grey
+    Output256   = 0x02,
+    /// Only the 6x6x6 color cube of the xterm-256 palette.  termbox offers no default color in this
+    /// mode; `Color::Default` is drawn as black.
+    Output216   = 0x03,
+    /// Only the 24 shades of grey of the xterm-256 palette.  As with `Output216`, `Color::Default`
+    /// is drawn as the darkest shade.
+    Grayscale   = 0x04,
+    /// No colors: every color is drawn as the terminal default, leaving only the attributes
+    /// (`RB_BOLD` etc.).  Useful on terminals without color support.  termbox itself runs in
+    /// `Normal` mode.
+    Monochrome,
+}
+
+impl OutputMode {
+    /// The mode to pass to `tb_select_output_mode`.
+    fn termbox_mode(self) -> c_int {
+        match self {
+            OutputMode::Monochrome => OutputMode::Normal as c_int,
+            mode => mode as c_int,
+        }
+    }
+}
+
+/// The colors a terminal claims to support, as guessed from the environment by
+/// `RustBox::output_capability`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum OutputCapability {
+    /// No colors at all (e.g. `TERM=dumb`).
+    Monochrome,
+    /// The 8 basic colors.
+    Colors8,
+    /// The xterm-256 palette.
+    Colors256,
+    /// 24-bit color.  termbox can't output it, so it is drawn with the 256-color palette.
+    Truecolor,
 }
 
-#[derive(Clone, Copy, PartialEq)]
-#[repr(C,u16)]
+impl OutputCapability {
+    /// The best output mode for a terminal with this capability.
+    pub fn output_mode(self) -> OutputMode {
+        match self {
+            OutputCapability::Monochrome => OutputMode::Monochrome,
+            OutputCapability::Colors8 => OutputMode::Normal,
+            OutputCapability::Colors256 | OutputCapability::Truecolor => OutputMode::Output256,
+        }
+    }
+}
+
+fn detect_capability(colorterm: Option<&str>, term: Option<&str>) -> OutputCapability {
+    if let Some(ct) = colorterm {
+        if ct == "truecolor" || ct == "24bit" {
+            return OutputCapability::Truecolor;
+        }
+    }
+    match term {
+        None | Some("") | Some("dumb") => OutputCapability::Monochrome,
+        Some(t) if t.contains("truecolor") || t.contains("direct") => OutputCapability::Truecolor,
+        Some(t) if t.contains("256color") => OutputCapability::Colors256,
+        Some(_) => OutputCapability::Colors8,
+    }
+}
+
+// Whether ambiguous-width characters are likely drawn two columns wide, given `$VTE_CJK_WIDTH`
+// (which VTE-based terminals honor) and the locale (the first of `$LC_ALL`, `$LC_CTYPE` and
+// `$LANG` that is set).  Terminals draw them wide in Chinese, Japanese and Korean locales.
+fn detect_ambiguous_wide(vte_cjk_width: Option<&str>, locale: Option<&str>) -> bool {
+    match vte_cjk_width {
+        Some("wide") | Some("1") => return true,
+        Some("narrow") | Some("0") => return false,
+        _ => { },
+    }
+    match locale {
+        Some(l) => l.starts_with("ja") || l.starts_with("zh") || l.starts_with("ko"),
+        None => false,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Color {
-    Default =  0x00,
-    Black =    0x01,
-    Red =      0x02,
-    Green =    0x03,
-    Yellow =   0x04,
-    Blue =     0x05,
-    Magenta =  0x06,
-    Cyan =     0x07,
-    White =    0x08,
+    /// The terminal's configured default color.  This is distinct from every other color, including
+    /// `Black` and `Byte(0)`, in all output modes but `Output216` and `Grayscale`.
+    Default,
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    /// An index into the xterm-256 palette.  In `OutputMode::Normal`, only the first 16 entries can
+    /// be displayed; they are mapped onto the basic colors.  In `Output216` and `Grayscale`, other
+    /// entries are drawn as the nearest available color.
+    Byte(u8),
+    /// A 24-bit color.  termbox cells only carry 16 bits of attributes, so this is rendered as
+    /// the nearest color available in the current output mode.
+    Rgb(u8, u8, u8),
+}
+
+const BASIC_COLORS: [Color; 8] = [
+    Color::Black, Color::Red, Color::Green, Color::Yellow,
+    Color::Blue, Color::Magenta, Color::Cyan, Color::White,
+];
+
+/// A 24-bit color.
+impl From<(u8, u8, u8)> for Color {
+    fn from((r, g, b): (u8, u8, u8)) -> Color {
+        Color::Rgb(r, g, b)
+    }
+}
+
+/// The error returned when parsing a `Color` fails.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseColorError {
+    input: String,
+}
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}: {:?}", self.description(), self.input)
+    }
+}
+
+impl Error for ParseColorError {
+    fn description(&self) -> &str {
+        "invalid hex color"
+    }
+}
+
+/// The code termbox interprets as the terminal's default color.
+const TB_DEFAULT: u16 = 0x00;
+
+/// The first entry of the xterm-256 color cube: black.
+const CUBE_BLACK: u16 = 0x10;
+
+/// The intensities of the six steps along each axis of the xterm-256 color cube.
+const CUBE_STEPS: [u8; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
+
+fn nearest_cube_step(v: u8) -> usize {
+    let mut best = 0;
+    for (i, &step) in CUBE_STEPS.iter().enumerate() {
+        if (step as i32 - v as i32).abs() < (CUBE_STEPS[best] as i32 - v as i32).abs() {
+            best = i;
+        }
+    }
+    best
+}
+
+/// The index of the shade in the xterm-256 grey ramp closest to the given 24-bit color.
+fn nearest_grey(r: u8, g: u8, b: u8) -> u8 {
+    let avg = (r as u32 + g as u32 + b as u32) / 3;
+    if avg < 8 { 0 } else { ::std::cmp::min((avg - 8) / 10, 23) as u8 }
+}
+
+/// The (approximate) 24-bit color of an entry in the xterm-256 palette.  The first 16 entries use
+/// xterm's defaults; most terminals let the user change them.
+fn palette_rgb(index: u8) -> (u8, u8, u8) {
+    const BASIC: [(u8, u8, u8); 16] = [
+        (0x00, 0x00, 0x00), (0xcd, 0x00, 0x00), (0x00, 0xcd, 0x00), (0xcd, 0xcd, 0x00),
+        (0x00, 0x00, 0xee), (0xcd, 0x00, 0xcd), (0x00, 0xcd, 0xcd), (0xe5, 0xe5, 0xe5),
+        (0x7f, 0x7f, 0x7f), (0xff, 0x00, 0x00), (0x00, 0xff, 0x00), (0xff, 0xff, 0x00),
+        (0x5c, 0x5c, 0xff), (0xff, 0x00, 0xff), (0x00, 0xff, 0xff), (0xff, 0xff, 0xff),
+    ];
+    match index {
+        0...15 => BASIC[index as usize],
+        16...231 => {
+            let i = index as usize - 16;
+            (CUBE_STEPS[i / 36], CUBE_STEPS[i / 6 % 6], CUBE_STEPS[i % 6])
+        },
+        _ => {
+            let level = 8 + (index - 232) * 10;
+            (level, level, level)
+        },
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> i32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32) * (x as i32 - y as i32);
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+/// Find the xterm-256 palette index closest to the given 24-bit color, considering both the color
+/// cube and the grey ramp.
+fn rgb_to_256(r: u8, g: u8, b: u8) -> u8 {
+    let (ri, gi, bi) = (nearest_cube_step(r), nearest_cube_step(g), nearest_cube_step(b));
+    let cube = (CUBE_STEPS[ri], CUBE_STEPS[gi], CUBE_STEPS[bi]);
+
+    let grey_index = nearest_grey(r, g, b);
+    let grey_level = 8 + grey_index * 10;
+    let grey = (grey_level, grey_level, grey_level);
+
+    if distance((r, g, b), grey) < distance((r, g, b), cube) {
+        232 + grey_index
+    } else {
+        16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+    }
+}
+
+impl Color {
+    /// The 8 basic colors, `Black` through `White`.
+    pub fn all() -> iter::Cloned<slice::Iter<'static, Color>> {
+        BASIC_COLORS.iter().cloned()
+    }
+
+    /// Every distinct color that can be drawn in `mode`, e.g. for a color picker: the basic
+    /// colors in `Normal`, the whole palette as `Byte`s in `Output256`, and the parts of the
+    /// palette termbox uses in `Output216` and `Grayscale`.  `Monochrome` (and `Current`) have
+    /// only `Default`.
+    pub fn all_in(mode: OutputMode) -> Vec<Color> {
+        match mode {
+            OutputMode::Normal => Color::all().collect(),
+            OutputMode::Output256 => (0..256).map(|b| Color::Byte(b as u8)).collect(),
+            OutputMode::Output216 => (16..232).map(Color::Byte).collect(),
+            OutputMode::Grayscale => (232..256).map(|b| Color::Byte(b as u8)).collect(),
+            OutputMode::Monochrome | OutputMode::Current => vec![Color::Default],
+        }
+    }
+
+    /// The name of the color's variant: `"Red"`, `"Default"`, `"Byte"`, ...
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Color::Default => "Default",
+            Color::Black => "Black",
+            Color::Red => "Red",
+            Color::Green => "Green",
+            Color::Yellow => "Yellow",
+            Color::Blue => "Blue",
+            Color::Magenta => "Magenta",
+            Color::Cyan => "Cyan",
+            Color::White => "White",
+            Color::Byte(_) => "Byte",
+            Color::Rgb(..) => "Rgb",
+        }
+    }
+
+    /// The entry at (r, g, b) in the 6x6x6 color cube of the xterm-256 palette, for `r`, `g` and
+    /// `b` in 0 through 5.
+    pub fn cube(r: u8, g: u8, b: u8) -> Option<Color> {
+        if r < 6 && g < 6 && b < 6 {
+            Some(Color::Byte(16 + 36 * r + 6 * g + b))
+        } else {
+            None
+        }
+    }
+
+    /// Shade `level` of the 24 shades of grey in the xterm-256 palette, for `level` in 0 (darkest)
+    /// through 23.
+    pub fn gray(level: u8) -> Option<Color> {
+        if level < 24 { Some(Color::Byte(232 + level)) } else { None }
+    }
+
+    /// Parse a 24-bit color written as `"#rrggbb"` or `"#rgb"` (case-insensitive).  Like any
+    /// `Color::Rgb`, it is drawn as the nearest color available in the current output mode.
+    pub fn from_hex(s: &str) -> Result<Color, ParseColorError> {
+        let err = || ParseColorError { input: s.to_string() };
+        if !s.starts_with('#') || !s[1..].chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(err());
+        }
+        let digits = &s[1..];
+        let channel = |i: usize, n: usize| {
+            let v = u8::from_str_radix(&digits[i * n..(i + 1) * n], 16).unwrap();
+            if n == 1 { v * 0x11 } else { v }
+        };
+        match digits.len() {
+            3 => Ok(Color::Rgb(channel(0, 1), channel(1, 1), channel(2, 1))),
+            6 => Ok(Color::Rgb(channel(0, 2), channel(1, 2), channel(2, 2))),
+            _ => Err(err()),
+        }
+    }
+
+    /// The termbox color code in `OutputMode::Normal`.
+    fn normal_code(self) -> u16 {
+        match self {
+            Color::Default => TB_DEFAULT,
+            Color::Black => 0x01,
+            Color::Red => 0x02,
+            Color::Green => 0x03,
+            Color::Yellow => 0x04,
+            Color::Blue => 0x05,
+            Color::Magenta => 0x06,
+            Color::Cyan => 0x07,
+            Color::White => 0x08,
+            Color::Byte(b) if b < 0x10 => (b % 8) as u16 + 1,
+            Color::Byte(_) => TB_DEFAULT,
+            Color::Rgb(r, g, b) => {
+                let bit = |v: u8, shift: u16| if v >= 0x80 { 1 << shift } else { 0 };
+                (bit(r, 0) | bit(g, 1) | bit(b, 2)) + 1
+            }
+        }
+    }
+
+    /// The 24-bit color this color is (approximately) drawn as, or `None` for `Default`.
+    fn rgb(self) -> Option<(u8, u8, u8)> {
+        match self {
+            Color::Default => None,
+            Color::Byte(b) => Some(palette_rgb(b)),
+            Color::Rgb(r, g, b) => Some((r, g, b)),
+            c => Some(palette_rgb(c.normal_code() as u8 - 1)),
+        }
+    }
+
+    /// The termbox color code for the given output mode.
+    fn code(self, mode: OutputMode) -> u16 {
+        let code = match mode {
+            // In these modes termbox takes an offset into its part of the palette.
+            OutputMode::Output216 => return match (self, self.rgb()) {
+                (Color::Byte(b), _) if b >= 16 && b < 232 => (b - 16) as u16,
+                (_, Some((r, g, b))) => {
+                    let (ri, gi, bi) = (nearest_cube_step(r), nearest_cube_step(g),
+                                        nearest_cube_step(b));
+                    (36 * ri + 6 * gi + bi) as u16
+                },
+                (_, None) => TB_DEFAULT,
+            },
+            OutputMode::Grayscale => return match (self, self.rgb()) {
+                (Color::Byte(b), _) if b >= 232 => (b - 232) as u16,
+                (_, Some((r, g, b))) => nearest_grey(r, g, b) as u16,
+                (_, None) => TB_DEFAULT,
+            },
+            OutputMode::Monochrome => return TB_DEFAULT,
+            OutputMode::Output256 => match self {
+                Color::Default => return TB_DEFAULT,
+                Color::Byte(b) => b as u16,
+                Color::Rgb(r, g, b) => rgb_to_256(r, g, b) as u16,
+                c => c.normal_code() - 1,
+            },
+            _ => return self.normal_code(),
+        };
+        // termbox treats code 0 as the terminal's default color in every output mode, so palette
+        // entry 0 (black) is drawn using the identical black from the color cube instead.
+        if code == TB_DEFAULT { CUBE_BLACK } else { code }
+    }
 }
 
 mod style {
+    use super::{Color, OutputMode};
+
     bitflags! {
         #[repr(C)]
+        #[derive(Debug)]
         flags Style: u16 {
             const TB_NORMAL_COLOR = 0x000F,
+            const TB_256_COLOR = 0x00FF,
             const RB_BOLD = 0x0100,
             const RB_UNDERLINE = 0x0200,
             const RB_REVERSE = 0x0400,
+            const RB_BLINK = 0x0800,
             const RB_NORMAL = 0x0000,
-            const TB_ATTRIB = RB_BOLD.bits | RB_UNDERLINE.bits | RB_REVERSE.bits,
+            const TB_ATTRIB = RB_BOLD.bits | RB_UNDERLINE.bits | RB_REVERSE.bits | RB_BLINK.bits,
+        }
+    }
+
+    /// Encode a style and a pair of colors as the foreground and background attributes termbox
+    /// expects.
+    pub fn encode(sty: Style, fg: Color, bg: Color, mode: OutputMode) -> (Style, Style) {
+        let fg = Style::from_color(fg, mode) | (sty & TB_ATTRIB & !RB_BLINK);
+        let mut bg = Style::from_color(bg, mode);
+        // termbox has no blink attribute of its own; it renders a bold background as blinking.
+        if sty.contains(RB_BLINK) {
+            bg = bg | RB_BOLD;
         }
+        (fg, bg)
     }
 
-    impl From<super::Color> for Style {
-        fn from(color: super::Color) -> Style {
-            Style { bits: color as u16 & TB_NORMAL_COLOR.bits }
+    impl Style {
+        /// Convert a color to a style for the given output mode.
+        pub fn from_color(color: Color, mode: OutputMode) -> Style {
+            let mask = match mode {
+                OutputMode::Output256 | OutputMode::Output216 | OutputMode::Grayscale => {
+                    TB_256_COLOR
+                },
+                _ => TB_NORMAL_COLOR,
+            };
+            Style { bits: color.code(mode) & mask.bits }
+        }
+    }
+
+    /// The color's code in `OutputMode::Normal`, which only has room for the basic colors.  In the
+    /// other modes, use `Style::from_color`; its result can be combined with `RB_BOLD` etc.
+    /// because termbox keeps attributes above the (at most 8-bit) color code in every mode.
+    impl From<Color> for Style {
+        fn from(color: Color) -> Style {
+            Style::from_color(color, OutputMode::Normal)
         }
     }
 }
 
-const NIL_RAW_EVENT: RawEvent = RawEvent { etype: 0, emod: 0, key: 0, ch: 0, w: 0, h: 0, x: 0, y: 0 };
+const NIL_RAW_EVENT: termbox::RawEvent = termbox::RawEvent {
+    etype: 0, emod: 0, key: 0, ch: 0, w: 0, h: 0, x: 0, y: 0
+};
 
-/// Unpack a RawEvent to an Event
-///
-/// if the `raw` parameter is true, then the Event variant will be the raw
-/// representation of the event.
-///     for instance KeyEventRaw instead of KeyEvent
+/// An event exactly as termbox reported it, returned by `poll_event_raw` and `peek_event_raw` for
+/// callers that want to decode events themselves.
+#[derive(Clone, Copy)]
+pub struct RawEvent(termbox::RawEvent);
+
+impl RawEvent {
+    /// The kind of event: 1 for a key press, 2 for a resize, 3 for a mouse event.
+    pub fn event_type(&self) -> u8 {
+        self.0.etype
+    }
+
+    /// The modifier bits of a key event.  0x01 is Alt; termbox reports no others.
+    pub fn modifier(&self) -> u8 {
+        self.0.emod
+    }
+
+    /// The key code of a key or mouse event (see the `key` module), or 0 if a key event carries a
+    /// character instead.
+    pub fn key(&self) -> u16 {
+        self.0.key
+    }
+
+    /// The character of a key event, or `None` if it carries a key code instead.
+    pub fn ch(&self) -> Option<char> {
+        if self.0.ch == 0 { None } else { char::from_u32(self.0.ch) }
+    }
+
+    /// The new width and height of a resize event.
+    pub fn size(&self) -> (i32, i32) {
+        (self.0.w, self.0.h)
+    }
+
+    /// The cell a mouse event happened at.
+    pub fn mouse_xy(&self) -> (i32, i32) {
+        (self.0.x, self.0.y)
+    }
+}
+
+impl fmt::Debug for RawEvent {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let ev = &self.0;
+        write!(fmt, "RawEvent {{ etype: {}, emod: {}, key: {:#x}, ch: {:#x}, w: {}, h: {}, x: {}, \
+                     y: {} }}", ev.etype, ev.emod, ev.key, ev.ch, ev.w, ev.h, ev.x, ev.y)
+    }
+}
+
+/// Translate an event read from termbox into an `Event`.
 ///
-/// This is useful if you want to interpret the raw event data yourself, rather
-/// than having rustbox translate it to its own representation.
-fn unpack_event(ev: RawEvent) -> Event {
+/// Events termbox reports but rustbox doesn't understand (an unknown event type or modifier, or a
+/// key event carrying an invalid character) are returned as `EventError::Decode` rather than
+/// aborting the program.
+fn unpack_event(ev: termbox::RawEvent) -> EventResult<Event> {
+    // Set by termbox on mouse events reporting motion; termbox-sys doesn't define it.
+    const TB_MOD_MOTION: u8 = 0x02;
+
     match ev.etype {
-        1 => Event::KeyEvent(match ev.emod {
+        // The modifier is decoded independently of the key so that it is attached to special keys
+        // (arrows, function keys, ...) as well as characters.
+        1 => Ok(Event::KeyEvent(match ev.emod {
             0 => None,
             1 => Some(Modifier::Alt),
-            _ => panic!("termbox returned an unknown modifier!")
+            m => return Err(decode_error(format!("termbox returned an unknown modifier: {}", m))),
         }, match ev.key {
-            0 => Key::Char(char::from_u32(ev.ch).unwrap()),
+            0 => match char::from_u32(ev.ch) {
+                Some(ch) => Key::Char(ch),
+                None => return Err(decode_error(
+                    format!("termbox returned an invalid character: {:#x}", ev.ch))),
+            },
             a => Key::Key(a),
+        })),
+        2 => Ok(Event::ResizeEvent { width: ev.w as usize, height: ev.h as usize }),
+        3 => Ok(match (ev.emod & TB_MOD_MOTION != 0, Key::Key(ev.key)) {
+            (false, button) => Event::MouseEvent { x: ev.x, y: ev.y, key: button },
+            (true, key::MOUSE_RELEASE) => Event::MouseMove { x: ev.x, y: ev.y },
+            (true, button) => Event::MouseDrag { x: ev.x, y: ev.y, key: button },
         }),
-        2 => Event::ResizeEvent(ev.w, ev.h),
-        _ => panic!("Unsupported event type"),
+        t => Err(decode_error(format!("termbox returned an unsupported event type: {}", t))),
     }
 }
 
-fn handle_error(ret: c_int) -> io::Result<bool> {
+fn decode_error(msg: String) -> EventError {
+    EventError::Decode(msg)
+}
+
+/// Convert a screen dimension reported by termbox to a `usize`.  termbox reports -1 while it isn't
+/// initialized (e.g. once the panic hook has shut it down), which is treated as an empty screen.
+fn dimension(d: c_int) -> usize {
+    if d < 0 { 0 } else { d as usize }
+}
+
+/// Convert a timeout to whole milliseconds, saturating at the largest timeout termbox accepts.
+fn timeout_millis(timeout: Duration) -> c_int {
+    let ms = timeout.as_millis();
+    if ms > c_int::max_value() as u128 {
+        c_int::max_value()
+    } else {
+        ms as c_int
+    }
+}
+
+/// Interpret the value returned by `tb_poll_event` or `tb_peek_event`: the type of the event that
+/// was read (1 = key, 2 = resize, 3 = mouse), or `None` if the wait timed out.
+fn handle_error(ret: c_int) -> EventResult<Option<u8>> {
     match ret {
-        -1 => Err(io::Error::last_os_error()),
-        0 => Ok(false),
-        1...2 => Ok(true),
-        _ => panic!("Unexpected value returned from termbox: {}", ret),
+        -1 => Err(EventError::from(io::Error::last_os_error())),
+        0 => Ok(None),
+        1...3 => Ok(Some(ret as u8)),
+        _ => Err(decode_error(format!("termbox returned an unexpected value: {}", ret))),
+    }
+}
+
+/// An error encountered while reading an event.
+#[derive(Debug)]
+pub enum EventError {
+    /// Waiting for input was interrupted by a signal (e.g. `SIGWINCH` on resize).  Nothing was
+    /// lost; just poll again.
+    Interrupted,
+    /// Reading input failed.
+    Io(io::Error),
+    /// termbox returned an event rustbox doesn't understand.  The event is dropped; polling again
+    /// returns the next one.
+    Decode(String),
+}
+
+pub type EventResult<T> = Result<T, EventError>;
+
+impl From<io::Error> for EventError {
+    fn from(err: io::Error) -> EventError {
+        if err.kind() == io::ErrorKind::Interrupted {
+            EventError::Interrupted
+        } else {
+            EventError::Io(err)
+        }
+    }
+}
+
+impl fmt::Display for EventError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EventError::Interrupted => write!(fmt, "{}", self.description()),
+            EventError::Io(ref err) => write!(fmt, "Failed to read an event: {}", err),
+            EventError::Decode(ref msg) => write!(fmt, "Failed to decode an event: {}", msg),
+        }
+    }
+}
+
+impl Error for EventError {
+    fn description(&self) -> &str {
+        match *self {
+            EventError::Interrupted => "Interrupted while waiting for an event.",
+            EventError::Io(_) => "Failed to read an event.",
+            EventError::Decode(_) => "Failed to decode an event.",
+        }
+    }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            EventError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Why `try_print` couldn't draw all of a string.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PrintError {
+    /// The starting position lies outside of the screen; nothing was drawn.
+    OutOfBounds,
+    /// The string ran past the edge of the screen.  Only the given number of columns were
+    /// drawn.
+    Truncated(usize),
+}
+
+impl fmt::Display for PrintError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PrintError::OutOfBounds => write!(fmt, "{}", self.description()),
+            PrintError::Truncated(written) => {
+                write!(fmt, "Text was truncated after {} columns.", written)
+            },
+        }
+    }
+}
+
+impl Error for PrintError {
+    fn description(&self) -> &str {
+        match *self {
+            PrintError::OutOfBounds => "Text was printed outside of the screen.",
+            PrintError::Truncated(_) => "Text was truncated at the edge of the screen.",
+        }
     }
 }
 
@@ -118,6 +856,9 @@ pub enum InitError {
     UnsupportedTerminal,
     FailedToOpenTty,
     PipeTrapError,
+    BufferStderrFailed,
+    /// termbox failed with an error code rustbox doesn't know.
+    Other(i32),
 }
 
 impl InitError {
@@ -127,14 +868,17 @@ impl InitError {
             -1 => UnsupportedTerminal,
             -2 => FailedToOpenTty,
             -3 => PipeTrapError,
-            _ => panic!("Unhandled termbox init error: {}", res),
+            _ => Other(res),
         }
     }
 }
 
 impl fmt::Display for InitError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "{}", self.description())
+        match *self {
+            InitError::Other(code) => write!(fmt, "termbox failed to initialize (error {}).", code),
+            _ => write!(fmt, "{}", self.description()),
+        }
     }
 }
 
@@ -146,16 +890,28 @@ impl Error for InitError {
             UnsupportedTerminal => "Unsupported terminal.",
             FailedToOpenTty => "Failed to open TTY.",
             PipeTrapError => "Pipe trap error.",
+            BufferStderrFailed => "Failed to buffer stderr.",
+            Other(_) => "termbox failed to initialize.",
         }
     }
 }
 
 mod running {
     use std::sync::atomic::{self, AtomicBool};
+    use std::thread;
+    use std::time::Duration;
+    use termbox;
+
+    // How often `run_blocking` checks whether the running RustBox has been dropped.
+    const RUN_POLL_MS: u64 = 5;
 
     // The state of the RustBox is protected by the lock.  Yay, global state!
     static RUSTBOX_RUNNING: AtomicBool = atomic::ATOMIC_BOOL_INIT;
 
+    // Whether termbox itself is initialized.  This can become false while the RustBox is still
+    // running when the panic hook shuts termbox down early.
+    static TERMBOX_INITIALIZED: AtomicBool = atomic::ATOMIC_BOOL_INIT;
+
     /// true iff RustBox is currently running.  Beware of races here--don't rely on this for anything
     /// critical unless you happen to know that RustBox cannot change state when it is called (a good
     /// usecase would be checking to see if it's worth risking double printing backtraces to avoid
@@ -179,6 +935,16 @@ mod running {
         }
     }
 
+    /// Like `run`, but wait for the running RustBox (if any) to be dropped rather than fail.
+    pub fn run_blocking() -> RunningGuard {
+        loop {
+            if let Some(guard) = run() {
+                return guard;
+            }
+            thread::sleep(Duration::from_millis(RUN_POLL_MS));
+        }
+    }
+
     impl Drop for RunningGuard {
         fn drop(&mut self) {
             // Indicate that we're free now.  We could probably get away with lower atomicity here,
@@ -186,112 +952,1461 @@ mod running {
             RUSTBOX_RUNNING.store(false, atomic::Ordering::SeqCst);
         }
     }
-}
 
-#[allow(missing_copy_implementations)]
-pub struct RustBox {
-    // RAII lock.
-    //
-    // Note that running *MUST* be the last field in the destructor, since destructors run in
-    // top-down order.  Otherwise it will not properly protect the above fields.
-    _running: running::RunningGuard,
-}
+    /// Record that termbox has been initialized.
+    pub fn initialized() {
+        TERMBOX_INITIALIZED.store(true, atomic::Ordering::SeqCst);
+    }
 
-impl RustBox {
-    /// Initialize rustbox.
-    ///
-    /// ```
-    /// use rustbox::RustBox;
-    /// let rb = RustBox::init();
-    /// ```
-    pub fn init() -> Result<RustBox, InitError> {
-        // Acquire RAII lock.  This might seem like overkill, but it is easy to forget to release
-        // it in the maze of error conditions below.
-        let running = try!(running::run().ok_or(InitError::AlreadyOpen));
+    /// true iff termbox is initialized.
+    pub fn is_initialized() -> bool {
+        TERMBOX_INITIALIZED.load(atomic::Ordering::SeqCst)
+    }
 
-        // Create the RustBox.
-        match unsafe { termbox::tb_init() } {
-            0 => Ok(RustBox { _running: running }),
-            res => Err(InitError::from_termbox_error(res)),
+    /// Shut termbox down unless that has already happened.  termbox aborts the process if it is
+    /// shut down twice.
+    pub fn shutdown() {
+        if TERMBOX_INITIALIZED.swap(false, atomic::Ordering::SeqCst) {
+            unsafe { termbox::tb_shutdown() }
         }
     }
+}
 
-    pub fn width(&self) -> usize {
-        unsafe { termbox::tb_width() as usize }
-    }
+/// How long `RustBox::visual_bell` keeps the screen reversed.
+const VISUAL_BELL_MS: u64 = 100;
 
-    pub fn height(&self) -> usize {
-        unsafe { termbox::tb_height() as usize }
-    }
+/// Options for `RustBox::init_with`.  These are applied before anything is drawn.
+///
+/// ```no_run
+/// use rustbox::{RustBox, InitOptions, InputMode, OutputMode};
+/// let rb = RustBox::init_with(InitOptions {
+///     input_mode: InputMode::Esc | InputMode::Mouse,
+///     output_mode: OutputMode::Output256,
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Clone, Copy)]
+pub struct InitOptions {
+    /// The input mode to select.  The default leaves termbox's default (`InputMode::Esc`) alone.
+    pub input_mode: InputModes,
+    /// The output mode to select.  The default leaves termbox's default (`OutputMode::Normal`)
+    /// alone.
+    pub output_mode: OutputMode,
+    /// Capture everything written to stderr while the RustBox is open and write it out once the
+    /// terminal has been restored.  Output is held in a pipe; once the pipe fills up further
+    /// writes to stderr fail until the RustBox is dropped.
+    pub buffer_stderr: bool,
+    /// Make the first event returned by `poll_event` (or `peek_event` etc.) a `ResizeEvent`
+    /// carrying the initial screen size, so the initial layout can be done by the same code that
+    /// handles later resizes.
+    pub emit_initial_resize: bool,
+    /// If a RustBox is already open (possibly on another thread), wait for it to be dropped
+    /// instead of failing with `InitError::AlreadyOpen`.
+    pub wait_if_open: bool,
+    /// Put the terminal in normal cursor and keypad mode, and decode the escape sequences for
+    /// arrows and other special keys that termbox doesn't recognize itself.
+    ///
+    /// termbox expects the sequences the terminal description (`$TERM`) lists, which are usually
+    /// those of application cursor mode.  Under a multiplexer such as tmux, or with a mismatched
+    /// `$TERM`, the terminal may send the normal mode sequences instead, and arrow keys arrive as
+    /// ESC followed by characters.  With this set, they are reported as `key::ARROW_UP` etc.
+    /// either way.
+    pub normalize_keys: bool,
+    /// Draw on the terminal's alternate screen, so that whatever was on the screen before is
+    /// restored when the RustBox is dropped.  This is the default.
+    ///
+    /// Without it, drawing happens on the normal screen.  As on the alternate screen, frames
+    /// cover the whole screen, replacing what was on it.  termbox clears the screen when it shuts
+    /// down, so when the RustBox is dropped the last frame is written out again, with its colors
+    /// and attributes, from the top of the screen; it stays visible, and in the scrollback, after
+    /// the program exits.
+    pub alternate_screen: bool,
+}
 
-    pub fn clear(&mut self) {
-        unsafe { termbox::tb_clear() }
+impl Default for InitOptions {
+    fn default() -> InitOptions {
+        InitOptions {
+            input_mode: InputModes::empty(),
+            output_mode: OutputMode::Current,
+            buffer_stderr: false,
+            emit_initial_resize: false,
+            wait_if_open: false,
+            normalize_keys: false,
+            alternate_screen: true,
+        }
+    }
+}
+
+#[allow(missing_copy_implementations)]
+pub struct RustBox {
+    // The output mode most recently selected, used to encode colors.
+    output_mode: OutputMode,
+
+    // The colors `clear` fills the screen with.  Kept so they can be re-encoded when the output
+    // mode changes.
+    clear_fg: Color,
+    clear_bg: Color,
+
+    // The screen size as of the last resize event.
+    size: (usize, usize),
+
+    // Whether Ctrl+letter key codes are decoded as `Modifier::Ctrl` events.
+    decode_ctrl: bool,
+
+    // Whether the back buffer is cleared when a resize event is decoded.
+    clear_on_resize: bool,
+
+    // How long to wait for the rest of an escape sequence after an ESC, if at all.
+    esc_timeout: Option<Duration>,
+
+    // Events to return before reading any more from termbox.
+    pending: VecDeque<Event>,
+
+    // Where events are logged as they are read, if anywhere.
+    recorder: Option<record::Recorder>,
+
+    // Where `log` writes to, if set.
+    log_file: Option<File>,
+
+    // The colors `print_role` draws with.
+    palette: Palette,
+
+    // Whether every color is drawn as the terminal default; see `set_no_color`.
+    no_color: bool,
+
+    // Whether ambiguous-width characters are measured as two columns wide.
+    ambiguous_wide: bool,
+
+    // The position the cursor was last shown at, restored by `show_cursor`.
+    cursor: (usize, usize),
+
+    // Whether the cursor is shown.  termbox starts with it hidden.
+    cursor_visible: bool,
+
+    // Whether frames are wrapped in synchronized output sequences.
+    synchronized: bool,
+
+    // Whether the terminal is in bracketed paste mode.
+    bracketed_paste: bool,
+
+    // Whether the terminal reports focus changes.
+    focus_reporting: bool,
+
+    // Whether the terminal reports mouse movement without a button held.
+    mouse_motion: bool,
+
+    // Whether the terminal is put in normal cursor key mode and escape sequences for special keys
+    // are decoded by rustbox; see `InitOptions::normalize_keys`.
+    normalize_keys: bool,
+
+    // Whether termbox draws on the alternate screen; see `InitOptions::alternate_screen`.
+    alternate_screen: bool,
+
+    // While suspended, the input mode to restore on resuming.
+    suspended: Option<InputModes>,
+
+    // The frame last drawn by `commit`, which the next one is diffed against.
+    committed: Option<CellBuffer>,
+
+    // Whether anything has been drawn or the cursor moved since the last `present`.
+    dirty: bool,
+
+    // The terminal termbox draws to, for checking its state.  `None` if it couldn't be opened.
+    tty: Option<tty::Tty>,
+
+    // Which terminal termbox draws to, for reinitializing termbox in `resume`.
+    target: tty::Target,
+
+    // Shared with any event sources, to serialize termbox calls.
+    termbox: event_source::Termbox,
+
+    // Held stderr output.  This is released after termbox has been shut down.
+    stderr: Option<stderr::Hold>,
+
+    // RAII lock.
+    //
+    // Note that running *MUST* be the last field in the destructor, since destructors run in
+    // top-down order.  Otherwise it will not properly protect the above fields.
+    _running: running::RunningGuard,
+}
+
+impl RustBox {
+    /// Initialize rustbox.
+    ///
+    /// ```
+    /// use rustbox::RustBox;
+    /// let rb = RustBox::init();
+    /// ```
+    pub fn init() -> Result<RustBox, InitError> {
+        RustBox::init_with(Default::default())
+    }
+
+    /// Initialize rustbox, first waiting for any RustBox that is already open to be dropped.  This
+    /// is `init_with` with `InitOptions::wait_if_open` set.
+    ///
+    /// Useful in tests, which may run on several threads at once, to take turns at the terminal.
+    pub fn init_blocking() -> Result<RustBox, InitError> {
+        RustBox::init_with(InitOptions { wait_if_open: true, ..Default::default() })
+    }
+
+    /// Install a panic hook that restores the terminal before the panic message is printed.
+    /// Otherwise the message (and backtrace) may be swallowed by the alternate screen, and the
+    /// terminal left in raw mode if the panic doesn't unwind.  The previously installed hook is
+    /// still run afterwards.
+    pub fn install_panic_hook() {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if running() {
+                running::shutdown();
+            }
+            previous(info);
+        }));
+    }
+
+    /// Initialize rustbox with the given options.
+    pub fn init_with(opts: InitOptions) -> Result<RustBox, InitError> {
+        RustBox::init_on(tty::Target::Default, opts)
+    }
+
+    /// Initialize rustbox on the terminal device at `path` (e.g. a pty) rather than the
+    /// controlling terminal.
+    pub fn init_file<P: AsRef<Path>>(path: P, opts: InitOptions) -> Result<RustBox, InitError> {
+        RustBox::init_on(tty::Target::File(path.as_ref().to_path_buf()), opts)
+    }
+
+    /// Initialize rustbox on an open terminal device rather than the controlling terminal.  `fd`
+    /// isn't closed when the RustBox is dropped; it must stay open until then.
+    pub fn init_fd(fd: RawFd, opts: InitOptions) -> Result<RustBox, InitError> {
+        RustBox::init_on(tty::Target::Fd(fd), opts)
+    }
+
+    fn init_on(target: tty::Target, opts: InitOptions) -> Result<RustBox, InitError> {
+        // Acquire RAII lock.  This might seem like overkill, but it is easy to forget to release
+        // it in the maze of error conditions below.  Every error returns before termbox is marked
+        // initialized, and termbox cleans up after itself when `tb_init` fails, so a failed
+        // init leaves nothing behind and can simply be retried.
+        let running = if opts.wait_if_open {
+            running::run_blocking()
+        } else {
+            try!(running::run().ok_or(InitError::AlreadyOpen))
+        };
+
+        let stderr = if opts.buffer_stderr {
+            Some(try!(stderr::Hold::stderr().map_err(|_| InitError::BufferStderrFailed)))
+        } else {
+            None
+        };
+
+        // Create the RustBox.
+        let mut rb = match unsafe { target.init() } {
+            0 => RustBox {
+                output_mode: OutputMode::Normal,
+                clear_fg: Color::Default,
+                clear_bg: Color::Default,
+                size: (0, 0),
+                decode_ctrl: false,
+                clear_on_resize: false,
+                esc_timeout: None,
+                pending: VecDeque::new(),
+                recorder: None,
+                log_file: None,
+                palette: Palette::default(),
+                no_color: env::var_os("NO_COLOR").map_or(false, |v| !v.is_empty()),
+                ambiguous_wide: RustBox::guess_ambiguous_wide(),
+                cursor: (0, 0),
+                cursor_visible: false,
+                synchronized: false,
+                bracketed_paste: false,
+                focus_reporting: false,
+                mouse_motion: false,
+                normalize_keys: opts.normalize_keys,
+                alternate_screen: opts.alternate_screen,
+                suspended: None,
+                committed: None,
+                dirty: false,
+                tty: target.open().ok(),
+                target,
+                termbox: Arc::new(Mutex::new(true)),
+                stderr,
+                _running: running,
+            },
+            res => return Err(InitError::from_termbox_error(res)),
+        };
+        running::initialized();
+        rb.write_terminal_modes(true);
+        rb.size = (rb.width(), rb.height());
+        rb.set_input_mode(opts.input_mode);
+        rb.set_output_mode(opts.output_mode);
+        if opts.emit_initial_resize {
+            rb.pending.push_back(Event::ResizeEvent { width: rb.size.0, height: rb.size.1 });
+        }
+        Ok(rb)
+    }
+
+    pub fn width(&self) -> usize {
+        let _tb = self.lock();
+        dimension(unsafe { termbox::tb_width() })
+    }
+
+    pub fn height(&self) -> usize {
+        let _tb = self.lock();
+        dimension(unsafe { termbox::tb_height() })
+    }
+
+    /// The screen size as (width, height).
+    ///
+    /// Unlike `width` and `height`, this doesn't query termbox: the size is cached and only
+    /// updated when a resize event is returned from `poll_event` or `peek_event`.
+    pub fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        self.committed = None;
+        self.dirty = true;
+        let _tb = self.lock();
+        unsafe { termbox::tb_clear() }
+    }
+
+    /// Clear the `w` by `h` rectangle with its top-left corner at (x, y), clipping it to the
+    /// screen.  The rectangle is filled with blanks in the colors set by `set_clear_attributes`,
+    /// just as `clear` fills the whole screen.
+    pub fn clear_region(&mut self, x: usize, y: usize, w: usize, h: usize) {
+        let (fg, bg) = self.encode(RB_NORMAL, self.clear_fg, self.clear_bg);
+        self.fill_raw(x, y, w, h, RawCell { ch: ' ' as u32, fg: fg.bits(), bg: bg.bits() });
+    }
+
+    /// Restore the terminal to its normal state, e.g. to run an editor or a shell in it, without
+    /// giving up the RustBox.  Call `resume` to take the terminal back.
+    ///
+    /// Nothing may be drawn and no events read while the RustBox is suspended.  stderr output
+    /// held because of `InitOptions::buffer_stderr` (including that of child processes) stays
+    /// held until the RustBox is dropped.
+    ///
+    /// ```no_run
+    /// # use rustbox::RustBox;
+    /// # use std::process::Command;
+    /// let mut rb = RustBox::init().unwrap();
+    /// rb.suspend();
+    /// Command::new("vi").status().unwrap();
+    /// rb.resume().unwrap();
+    /// ```
+    pub fn suspend(&mut self) {
+        if self.suspended.is_some() {
+            return;
+        }
+        self.suspended = Some(self.get_input_mode());
+        self.write_terminal_modes(false);
+        let _tb = self.lock_raw();
+        running::shutdown();
+    }
+
+    /// Take the terminal back after `suspend`, restoring the input and output modes, the cursor
+    /// and the terminal modes set through this `RustBox`.  The back buffer starts out blank, so
+    /// redraw everything.  If the screen size changed in the meantime, a `ResizeEvent` is queued.
+    pub fn resume(&mut self) -> Result<(), InitError> {
+        let input_mode = match self.suspended {
+            Some(mode) => mode,
+            None => return Ok(()),
+        };
+        {
+            let _tb = self.lock_raw();
+            match unsafe { self.target.init() } {
+                0 => { },
+                res => return Err(InitError::from_termbox_error(res)),
+            }
+        }
+        running::initialized();
+        self.suspended = None;
+        self.committed = None;
+        self.set_input_mode(input_mode);
+        let mode = self.output_mode;
+        self.set_output_mode(mode);
+        if self.cursor_visible {
+            self.show_cursor();
+        }
+        self.write_terminal_modes(true);
+        let size = (self.width(), self.height());
+        if size != self.size {
+            self.size = size;
+            self.pending.push_back(Event::ResizeEvent { width: size.0, height: size.1 });
+        }
+        Ok(())
+    }
+
+    /// Draw a complete frame: clear the back buffer, draw into it with `f`, then present it.
+    ///
+    /// ```no_run
+    /// # use rustbox::{RustBox, Color, RB_NORMAL};
+    /// let mut rb = RustBox::init().unwrap();
+    /// rb.frame(|rb| {
+    ///     rb.print(0, 0, RB_NORMAL, Color::White, Color::Default, "Hello");
+    /// });
+    /// ```
+    pub fn frame<F: FnOnce(&mut RustBox)>(&mut self, f: F) {
+        self.clear();
+        f(self);
+        self.present();
+    }
+
+    /// Set the colors `clear` fills the screen with.
+    pub fn set_clear_attributes(&mut self, fg: Color, bg: Color) {
+        self.clear_fg = fg;
+        self.clear_bg = bg;
+        let (fg, bg) = self.encode(RB_NORMAL, fg, bg);
+        let _tb = self.lock();
+        unsafe { termbox::tb_set_clear_attributes(fg.bits(), bg.bits()) }
     }
 
     pub fn present(&mut self) {
-        unsafe { termbox::tb_present() }
+        if self.synchronized {
+            if let Some(ref mut tty) = self.tty {
+                let _ = tty.write(b"\x1b[?2026h");
+                tty.queue(b"\x1b[?2026l");
+            }
+        }
+        {
+            let _tb = self.lock();
+            unsafe { termbox::tb_present() }
+        }
+        self.dirty = false;
+        // Anything queued for the terminal goes out after the frame, so termbox's own output
+        // can't interleave with it.
+        if let Some(ref mut tty) = self.tty {
+            let _ = tty.flush();
+        }
+    }
+
+    /// Draw `buffer` as the next frame and present it, writing only the cells that differ from the
+    /// frame passed to the previous `commit`.  This saves a call into termbox for each unchanged
+    /// cell when frames are composed off-screen.
+    ///
+    /// The buffer is clipped to the screen.  The diff is taken against the last committed frame,
+    /// not the back buffer, so anything drawn directly onto the screen between commits may not be
+    /// overwritten; `clear` (or a resize) makes the next commit redraw every cell.
+    pub fn commit(&mut self, buffer: &CellBuffer) {
+        let previous = self.committed.take();
+        {
+            let _tb = self.lock();
+            unsafe {
+                let (w, h) = (dimension(termbox::tb_width()), dimension(termbox::tb_height()));
+                for (x, y, c) in cell::changed_cells(previous.as_ref(), buffer, w, h) {
+                    termbox::tb_change_cell(x as c_int, y as c_int, c.ch as u32, c.fg.bits(),
+                                            c.bg.bits());
+                }
+            }
+        }
+        self.committed = Some(buffer.clone());
+        self.present();
+    }
+
+    /// Whether anything has been drawn to the back buffer (or the cursor moved or hidden) since the
+    /// last `present`.  If not, presenting would change nothing on the screen, so a render loop
+    /// can skip it and wait longer for input.
+    ///
+    /// This tracks drawing calls, not their effect: redrawing a cell with the same contents still
+    /// makes the `RustBox` dirty.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Wrap each `present` in the synchronized output sequences (DEC private mode 2026), so that
+    /// terminals supporting them draw the whole frame at once instead of tearing.  Terminals that
+    /// don't support them ignore them.
+    pub fn set_synchronized(&mut self, synchronized: bool) {
+        self.synchronized = synchronized;
+    }
+
+    /// Send the output queued for the terminal (by `set_title`, `bell` etc.) now rather than with
+    /// the next `present`.
+    ///
+    /// termbox buffers everything it draws and only writes it out in `present`, so output sent
+    /// by `flush` always lands between two frames, never in the middle of one.  Queued output
+    /// keeps the order it was queued in; anything queued after a `flush` waits for the next
+    /// `flush` or `present`.
+    pub fn flush(&mut self) {
+        if let Some(ref mut tty) = self.tty {
+            let _ = tty.flush();
+        }
+    }
+
+    /// Set the terminal's window (or tab) title.  The title is sent with the next `present`.
+    /// Control characters in `title` are dropped.
+    pub fn set_title(&mut self, title: &str) {
+        if let Some(ref mut tty) = self.tty {
+            let title: String = title.chars().filter(|c| !c.is_control()).collect();
+            tty.queue(format!("\x1b]0;{}\x07", title).as_bytes());
+        }
+    }
+
+    /// Ring the terminal bell.  The bell is sent with the next `present`.
+    pub fn bell(&mut self) {
+        if let Some(ref mut tty) = self.tty {
+            tty.queue(b"\x07");
+        }
+    }
+
+    /// Flash the screen by briefly switching the terminal to reverse video, for terminals (or
+    /// users) with the audible bell turned off.  This blocks for the duration of the flash, about
+    /// a tenth of a second.
+    pub fn visual_bell(&mut self) {
+        if let Some(ref mut tty) = self.tty {
+            tty.queue(b"\x1b[?5h");
+            let _ = tty.flush();
+            thread::sleep(Duration::from_millis(VISUAL_BELL_MS));
+            tty.queue(b"\x1b[?5l");
+            let _ = tty.flush();
+        }
+    }
+
+    /// Like `present`, but check afterwards that the terminal is still connected.
+    ///
+    /// termbox doesn't report write errors, so output to a terminal that has gone away (e.g. a
+    /// dropped SSH connection) is silently discarded.  This returns an error once the terminal
+    /// has been hung up, so long-running programs can notice and exit.
+    pub fn present_checked(&mut self) -> io::Result<()> {
+        self.present();
+        match self.tty {
+            Some(ref tty) => tty.check(),
+            None => Err(io::Error::new(io::ErrorKind::Other, "the terminal could not be opened")),
+        }
     }
 
-    pub fn set_cursor(&mut self, x: isize, y: isize) {
+    /// Move the cursor to (x, y) and show it.  Positions outside of the screen are clamped to
+    /// its last row and column.
+    pub fn set_cursor(&mut self, x: usize, y: usize) {
+        let x = cmp::min(x, self.width().saturating_sub(1));
+        let y = cmp::min(y, self.height().saturating_sub(1));
+        self.cursor = (x, y);
+        self.cursor_visible = true;
+        self.dirty = true;
+        let _tb = self.lock();
         unsafe { termbox::tb_set_cursor(x as c_int, y as c_int) }
     }
 
+    /// Hide the cursor.  It stays hidden until it is moved with `set_cursor` or `show_cursor`.
+    pub fn hide_cursor(&mut self) {
+        self.cursor_visible = false;
+        self.dirty = true;
+        let _tb = self.lock();
+        unsafe { termbox::tb_set_cursor(termbox::TB_HIDE_CURSOR, termbox::TB_HIDE_CURSOR) }
+    }
+
+    /// Show the cursor at the position it was last moved to.
+    pub fn show_cursor(&mut self) {
+        let (x, y) = self.cursor;
+        self.set_cursor(x, y);
+    }
+
+    /// The position of the cursor, or `None` if it is hidden.
+    pub fn cursor_position(&self) -> Option<(usize, usize)> {
+        if self.cursor_visible { Some(self.cursor) } else { None }
+    }
+
+    /// Write a cell to the back buffer from raw termbox attributes, without checking that (x, y)
+    /// lies on the screen.  See `change_cell_checked` for a safe version.
     pub unsafe fn change_cell(&mut self, x: usize, y: usize, ch: u32, fg: u16, bg: u16) {
+        self.dirty = true;
+        let _tb = self.lock();
         termbox::tb_change_cell(x as c_int, y as c_int, ch, fg, bg)
     }
 
-    pub fn print(&mut self, x: usize, y: usize, sty: Style, fg: Color, bg: Color, s: &str) {
-        let fg = Style::from(fg) | (sty & style::TB_ATTRIB);
-        let bg = Style::from(bg);
-        for (i, ch) in s.chars().enumerate() {
+    /// A safe `change_cell`: write `ch` at (x, y) with the given colors and attributes, encoded
+    /// for the current output mode.  Returns whether (x, y) lies on the screen; writes outside of
+    /// it are ignored.
+    pub fn change_cell_checked(&mut self, x: usize, y: usize, ch: char, fg: Color, bg: Color,
+                               sty: Style) -> bool {
+        if x >= self.width() || y >= self.height() {
+            return false;
+        }
+        let (fg, bg) = self.encode(sty, fg, bg);
+        unsafe {
+            self.change_cell(x, y, ch as u32, fg.bits(), bg.bits());
+        }
+        true
+    }
+
+    /// Read the cell at (x, y) from the back buffer, or `None` if it lies outside of the screen.
+    pub fn cell(&self, x: usize, y: usize) -> Option<Cell> {
+        let _tb = self.lock();
+        unsafe {
+            let (w, h) = (dimension(termbox::tb_width()), dimension(termbox::tb_height()));
+            if x >= w || y >= h {
+                return None;
+            }
+            let buffer = termbox::tb_cell_buffer();
+            Some(cell::from_raw(&*buffer.offset((y * w + x) as isize)))
+        }
+    }
+
+    /// The text of row `y` of the back buffer, with trailing blanks trimmed, e.g. to check what
+    /// was drawn in a test.  The blank `print` leaves after each wide character is skipped.  Rows
+    /// outside of the screen are empty.
+    pub fn row_text(&self, y: usize) -> String {
+        let _tb = self.lock();
+        unsafe {
+            let (w, h) = (dimension(termbox::tb_width()), dimension(termbox::tb_height()));
+            if y >= h {
+                return String::new();
+            }
+            let row = slice::from_raw_parts(termbox::tb_cell_buffer().offset((y * w) as isize), w);
+            cell::row_text(row.iter().map(|raw| cell::from_raw(raw).ch), self.width_fn())
+        }
+    }
+
+    /// Write a cell to the back buffer.  Writes outside of the screen are ignored.
+    pub fn set_cell(&mut self, x: usize, y: usize, cell: Cell) {
+        if x >= self.width() || y >= self.height() {
+            return;
+        }
+        unsafe {
+            self.change_cell(x, y, cell.ch as u32, cell.fg.bits(), cell.bg.bits());
+        }
+    }
+
+    /// Change the colors and attributes of the cell at (x, y) in the back buffer, keeping its
+    /// character, e.g. to highlight a selection drawn earlier.  Cells outside of the screen are
+    /// ignored.
+    pub fn set_cell_style(&mut self, x: usize, y: usize, fg: Color, bg: Color, sty: Style) {
+        let (fg, bg) = self.encode(sty, fg, bg);
+        {
+            let _tb = self.lock();
+            unsafe {
+                let (w, h) = (dimension(termbox::tb_width()), dimension(termbox::tb_height()));
+                if x >= w || y >= h {
+                    return;
+                }
+                let cell = &mut *termbox::tb_cell_buffer().offset((y * w + x) as isize);
+                cell.fg = fg.bits();
+                cell.bg = bg.bits();
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Copy an off-screen buffer onto the back buffer with its top-left corner at (x, y),
+    /// clipping it to the screen.
+    pub fn blit(&mut self, x: usize, y: usize, src: &CellBuffer) {
+        self.dirty = true;
+        let _tb = self.lock();
+        unsafe {
+            let (w, h) = (dimension(termbox::tb_width()), dimension(termbox::tb_height()));
+            if x >= w || y >= h {
+                return;
+            }
+            let cols = cmp::min(src.width(), w - x);
+            let rows = cmp::min(src.height(), h - y);
+            let buffer = termbox::tb_cell_buffer();
+            for row in 0..rows {
+                let dst = buffer.offset(((y + row) * w + x) as isize);
+                for (col, c) in src.row(row)[..cols].iter().enumerate() {
+                    *dst.offset(col as isize) = cell::to_raw(c);
+                }
+            }
+        }
+    }
+
+    /// Copy the whole back buffer, e.g. to put it back with `restore` once a popup drawn over it
+    /// has been closed.
+    pub fn snapshot(&self) -> CellBuffer {
+        let _tb = self.lock();
+        unsafe {
+            let (w, h) = (dimension(termbox::tb_width()), dimension(termbox::tb_height()));
+            if w * h == 0 {
+                return cell::buffer_from_raw(w, h, &[]);
+            }
+            let raw = slice::from_raw_parts(termbox::tb_cell_buffer(), w * h);
+            cell::buffer_from_raw(w, h, raw)
+        }
+    }
+
+    /// Replace the back buffer with a snapshot taken by `snapshot`.
+    ///
+    /// If the screen has been resized since, the snapshot is clipped to the new size and any area
+    /// it doesn't cover is cleared.
+    pub fn restore(&mut self, snapshot: &CellBuffer) {
+        if snapshot.width() < self.width() || snapshot.height() < self.height() {
+            self.clear();
+        }
+        self.blit(0, 0, snapshot);
+    }
+
+    /// Shift the whole back buffer up by `dy` rows (down if `dy` is negative) and fill the rows
+    /// this exposes with `fill`.  Shifting by the screen height or more clears the buffer.
+    ///
+    /// This is cheaper than redrawing everything when, say, a log view gains a line at the
+    /// bottom: scroll by 1 and draw only the new line.
+    pub fn scroll(&mut self, dy: isize, fill: Cell) {
+        let raw = cell::to_raw(&fill);
+        let (width, height) = (self.width(), self.height());
+        let n = cmp::min(dy.unsigned_abs(), height);
+        if n == 0 || width == 0 {
+            return;
+        }
+        self.dirty = true;
+        {
+            let _tb = self.lock();
             unsafe {
-                self.change_cell(x+i, y, ch as u32, fg.bits(), bg.bits());
+                let buffer = termbox::tb_cell_buffer();
+                let kept = (height - n) * width;
+                if dy > 0 {
+                    ptr::copy(buffer.offset((n * width) as isize), buffer, kept);
+                } else {
+                    ptr::copy(buffer, buffer.offset((n * width) as isize), kept);
+                }
+            }
+        }
+        let top = if dy > 0 { height - n } else { 0 };
+        self.fill_raw(0, top, width, n, raw);
+    }
+
+    /// Fill the `w` by `h` rectangle with its top-left corner at (x, y) with `ch`, clipping it to
+    /// the screen.
+    pub fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize,
+                     sty: Style, fg: Color, bg: Color, ch: char) {
+        let (fg, bg) = self.encode(sty, fg, bg);
+        self.fill_raw(x, y, w, h, RawCell { ch: ch as u32, fg: fg.bits(), bg: bg.bits() });
+    }
+
+    /// Draw a horizontal run of `len` copies of `ch` starting at (x, y), clipping it to the screen.
+    pub fn hline(&mut self, x: usize, y: usize, len: usize, sty: Style, fg: Color, bg: Color,
+                 ch: char) {
+        self.fill_rect(x, y, len, 1, sty, fg, bg, ch);
+    }
+
+    /// Draw a vertical run of `len` copies of `ch` starting at (x, y), clipping it to the screen.
+    pub fn vline(&mut self, x: usize, y: usize, len: usize, sty: Style, fg: Color, bg: Color,
+                 ch: char) {
+        self.fill_rect(x, y, 1, len, sty, fg, bg, ch);
+    }
+
+    /// Draw a border around the `w` by `h` rectangle with its top-left corner at (x, y), clipping
+    /// it to the screen.  The border occupies the outermost rows and columns of the rectangle,
+    /// which must be at least 2 by 2.
+    pub fn draw_border(&mut self, x: usize, y: usize, w: usize, h: usize,
+                       sty: Style, fg: Color, bg: Color, border: BorderStyle) {
+        if w < 2 || h < 2 {
+            return;
+        }
+        let (tl, tr, bl, br, horiz, vert) = border.chars();
+        let (right, bottom) = (x + w - 1, y + h - 1);
+        self.fill_rect(x + 1, y, w - 2, 1, sty, fg, bg, horiz);
+        self.fill_rect(x + 1, bottom, w - 2, 1, sty, fg, bg, horiz);
+        self.fill_rect(x, y + 1, 1, h - 2, sty, fg, bg, vert);
+        self.fill_rect(right, y + 1, 1, h - 2, sty, fg, bg, vert);
+        self.fill_rect(x, y, 1, 1, sty, fg, bg, tl);
+        self.fill_rect(right, y, 1, 1, sty, fg, bg, tr);
+        self.fill_rect(x, bottom, 1, 1, sty, fg, bg, bl);
+        self.fill_rect(right, bottom, 1, 1, sty, fg, bg, br);
+    }
+
+    /// Draw a horizontal progress bar `width` cells wide at (x, y), filled in `fg` from the left
+    /// in proportion to `ratio` and `bg` elsewhere.  The end of the bar is drawn with a partial
+    /// block, so it moves in steps of an eighth of a cell.  `ratio` is clamped to 0.0 through 1.0.
+    pub fn draw_gauge(&mut self, x: usize, y: usize, width: usize, ratio: f32, fg: Color,
+                      bg: Color) {
+        const PARTIAL: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+        let ratio = if ratio > 1.0 { 1.0 } else if ratio > 0.0 { ratio } else { 0.0 };
+        let eighths = (ratio as f64 * width as f64 * 8.0).round() as usize;
+        let (full, partial) = (eighths / 8, eighths % 8);
+        self.fill_rect(x, y, full, 1, RB_NORMAL, fg, bg, '█');
+        if full < width {
+            self.fill_rect(x + full, y, 1, 1, RB_NORMAL, fg, bg, PARTIAL[partial]);
+            self.fill_rect(x + full + 1, y, width - full - 1, 1, RB_NORMAL, fg, bg, ' ');
+        }
+    }
+
+    /// Set a "pixel" of a canvas twice as tall as the screen: each cell holds two pixels, one
+    /// above the other, drawn with the half-block characters `▀` and `▄`.  Pixel (x, y) is the
+    /// top half of cell (x, y / 2) when `y` is even and the bottom half otherwise.  The other
+    /// half of the cell keeps its color; a cell that isn't a half block counts as two pixels of
+    /// its background color.  Pixels outside of the screen are ignored.
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let old = match self.cell(x, y / 2) {
+            Some(cell) => cell,
+            None => return,
+        };
+        let (fg, bg) = (old.fg & style::TB_256_COLOR, old.bg & style::TB_256_COLOR);
+        let (top, bottom) = match old.ch {
+            '▀' => (fg, bg),
+            '▄' => (bg, fg),
+            _ => (bg, bg),
+        };
+        let (pixel, _) = self.encode(RB_NORMAL, color, Color::Default);
+        let (top, bottom) = if y % 2 == 0 { (pixel, bottom) } else { (top, pixel) };
+        let cell = if top == bottom {
+            Cell::new(' ', top, bottom)
+        } else {
+            Cell::new('▀', top, bottom)
+        };
+        self.set_cell(x, y / 2, cell);
+    }
+
+    /// Draw everything in the terminal's default colors, whatever colors are asked for, so that
+    /// the output is monochrome.  Attributes such as `RB_BOLD` still apply.  This is turned on at
+    /// initialization if the `NO_COLOR` environment variable is set (see https://no-color.org).
+    ///
+    /// Cells already drawn keep their colors, apart from blanks left by `clear`.  Raw attributes
+    /// passed to `change_cell` are drawn as given.
+    pub fn set_no_color(&mut self, no_color: bool) {
+        self.no_color = no_color;
+        let (fg, bg) = (self.clear_fg, self.clear_bg);
+        self.set_clear_attributes(fg, bg);
+    }
+
+    pub fn no_color(&self) -> bool {
+        self.no_color
+    }
+
+    /// Set the colors `print_role` draws with.  Anything already drawn keeps its colors.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Like `print`, but with the colors the palette assigns to the `fg` and `bg` roles.
+    ///
+    /// ```no_run
+    /// # use rustbox::{RustBox, Role, RB_BOLD};
+    /// let mut rb = RustBox::init().unwrap();
+    /// rb.print_role(0, 0, RB_BOLD, Role::Error, Role::Background, "file not found");
+    /// ```
+    pub fn print_role(&mut self, x: usize, y: usize, sty: Style, fg: Role, bg: Role, s: &str)
+                      -> usize {
+        let (fg, bg) = (self.palette.color(fg), self.palette.color(bg));
+        self.print(x, y, sty, fg, bg, s)
+    }
+
+    /// Print a string starting at (x, y), clipping it at the edge of the screen.
+    ///
+    /// Wide characters occupy two columns; the second is left blank.  Returns the number of
+    /// columns written; this is less than `text_width(s)` if the string was truncated.
+    pub fn print(&mut self, x: usize, y: usize, sty: Style, fg: Color, bg: Color, s: &str) -> usize {
+        let width = self.width_fn();
+        self.print_measured(x, y, sty, fg, bg, s.chars().map(|ch| (ch, width(ch))))
+    }
+
+    /// Like `print`, but report whether the whole string fit, e.g. so a layout can widen a
+    /// column whose contents didn't.  The string is drawn (and clipped) exactly as `print` would
+    /// draw it either way.
+    pub fn try_print(&mut self, x: usize, y: usize, sty: Style, fg: Color, bg: Color, s: &str)
+                     -> Result<usize, PrintError> {
+        if x >= self.width() || y >= self.height() {
+            return Err(PrintError::OutOfBounds);
+        }
+        let written = self.print(x, y, sty, fg, bg, s);
+        if written < self.string_width(s) {
+            Err(PrintError::Truncated(written))
+        } else {
+            Ok(written)
+        }
+    }
+
+    /// Print text that has been measured in advance.  This behaves exactly like `print`, but
+    /// avoids decoding and measuring the string on every call.
+    pub fn print_prepared(&mut self, x: usize, y: usize, sty: Style, fg: Color, bg: Color,
+                          text: &PreparedText) -> usize {
+        let chars = text.chars().iter().map(|&(ch, w)| (ch, w as usize));
+        self.print_measured(x, y, sty, fg, bg, chars)
+    }
+
+    /// Print a sequence of differently styled strings one after another on row `y`, starting at
+    /// column `x` and clipping them at the edge of the screen.  Returns the total number of
+    /// columns written.
+    ///
+    /// ```no_run
+    /// # use rustbox::{RustBox, Color, RB_BOLD, RB_NORMAL};
+    /// # let mut rb = RustBox::init().unwrap();
+    /// rb.print_spans(0, 0, &[(RB_BOLD, Color::Blue, Color::Default, "fn"),
+    ///                        (RB_NORMAL, Color::Default, Color::Default, " main() {")]);
+    /// ```
+    pub fn print_spans(&mut self, x: usize, y: usize, spans: &[(Style, Color, Color, &str)])
+                       -> usize {
+        let mut written = 0;
+        for &(sty, fg, bg, s) in spans {
+            let w = self.print(x + written, y, sty, fg, bg, s);
+            written += w;
+            if w < self.string_width(s) {
+                break;
             }
         }
+        written
+    }
+
+    /// Print a string starting at (x, y), word-wrapping it to lines of at most `max_width`
+    /// columns.  Returns the number of rows used.
+    pub fn print_wrapped(&mut self, x: usize, y: usize, max_width: usize,
+                         sty: Style, fg: Color, bg: Color, s: &str) -> usize {
+        let lines = text::wrap(s, max_width);
+        for (i, line) in lines.iter().enumerate() {
+            self.print(x, y + i, sty, fg, bg, line);
+        }
+        lines.len()
+    }
+
+    /// Print a string at the start of row `y` and fill the rest of the row, up to the edge of the
+    /// screen, with spaces in `bg`, e.g. for a status or title bar.  Returns the number of columns
+    /// the string itself occupies, as `print` does.
+    pub fn print_line(&mut self, y: usize, sty: Style, fg: Color, bg: Color, s: &str) -> usize {
+        let written = self.print(0, y, sty, fg, bg, s);
+        let width = self.width();
+        self.fill_rect(written, y, width - written, 1, sty, fg, bg, ' ');
+        written
     }
 
     pub fn print_char(&mut self, x: usize, y: usize, sty: Style, fg: Color, bg: Color, ch: char) {
-        let fg = Style::from(fg) | (sty & style::TB_ATTRIB);
-        let bg = Style::from(bg);
+        let (fg, bg) = self.encode(sty, fg, bg);
         unsafe {
             self.change_cell(x, y, ch as u32, fg.bits(), bg.bits());
         }
     }
 
-    pub fn poll_event_raw(&mut self) -> io::Result<RawEvent> {
-        let mut ev = NIL_RAW_EVENT;
-        assert!(try!(handle_error(unsafe {
-            termbox::tb_poll_event(&mut ev as *mut RawEvent)
-        })) == true); // We must have a result
+    /// Block until an event arrives and return it undecoded.
+    pub fn poll_event_raw(&mut self) -> EventResult<RawEvent> {
+        // Wait in slices, as event sources do, releasing the termbox lock in between so that an
+        // event source on another thread isn't shut out until an event arrives.
+        let slice = Duration::from_millis(event_source::POLL_SLICE_MS);
+        loop {
+            if let Some(ev) = try!(self.peek_event_raw(slice)) {
+                return Ok(ev);
+            }
+            event_source::give_way();
+        }
+    }
+
+    /// Block until an event arrives.  If a signal arrives first, this returns
+    /// `EventError::Interrupted` and can simply be called again.
+    pub fn poll_event(&mut self) -> EventResult<Event> {
+        let ev = match self.pending.pop_front() {
+            Some(ev) => ev,
+            None => {
+                let raw = try!(self.poll_event_raw());
+                let ev = try!(self.decode_event(raw));
+                try!(self.reassemble(ev))
+            },
+        };
+        self.record(&ev);
         Ok(ev)
     }
 
-    pub fn poll_event(&mut self) -> io::Result<Event> {
-        self.poll_event_raw().map(unpack_event)
+    /// An iterator that blocks on `poll_event` for each item.  It never ends on its own; poll
+    /// errors are yielded as `Err` items.
+    ///
+    /// ```no_run
+    /// # use rustbox::{RustBox, Event, Key};
+    /// let mut rb = RustBox::init().unwrap();
+    /// for ev in rb.events() {
+    ///     match ev {
+    ///         Ok(Event::KeyEvent(_, Key::Char('q'))) => break,
+    ///         _ => { }
+    ///     }
+    /// }
+    /// ```
+    pub fn events(&mut self) -> EventIterator {
+        EventIterator { rb: self }
     }
 
-    pub fn peek_event_raw(&mut self, timeout: Duration) -> io::Result<Option<RawEvent>> {
+    pub fn peek_event_raw(&mut self, timeout: Duration) -> EventResult<Option<RawEvent>> {
         let mut ev = NIL_RAW_EVENT;
+        let _tb = self.lock();
         handle_error(unsafe {
-            termbox::tb_peek_event(&mut ev as *mut RawEvent, timeout.num_milliseconds() as c_int)
-        }).map(|v| if v { Some(ev) } else { None })
+            termbox::tb_peek_event(&mut ev as *mut termbox::RawEvent, timeout_millis(timeout))
+        }).map(|etype| etype.map(|_| RawEvent(ev)))
     }
 
-    pub fn peek_event(&mut self, timeout: Duration) -> io::Result<Option<Event>> {
-        self.peek_event_raw(timeout).map(|ev| ev.map(unpack_event))
+    pub fn peek_event(&mut self, timeout: Duration) -> EventResult<Option<Event>> {
+        let ev = match self.pending.pop_front() {
+            Some(ev) => ev,
+            None => match try!(self.peek_event_raw(timeout)) {
+                Some(raw) => {
+                    let ev = try!(self.decode_event(raw));
+                    try!(self.reassemble(ev))
+                },
+                None => return Ok(None),
+            },
+        };
+        self.record(&ev);
+        Ok(Some(ev))
     }
 
-    pub fn set_input_mode(&mut self, mode: InputMode) {
-        unsafe {
-            termbox::tb_select_input_mode(mode as c_int);
+    /// Block until an event arrives or `timeout` has passed, whichever is first; `None` means the
+    /// timeout expired.  This is the same as `peek_event`, under a name that says what it does.
+    ///
+    /// ```no_run
+    /// # use rustbox::RustBox;
+    /// # use std::time::Duration;
+    /// let mut rb = RustBox::init().unwrap();
+    /// loop {
+    ///     match rb.poll_event_timeout(Duration::from_secs(1)).unwrap() {
+    ///         Some(ev) => { /* handle the event */ },
+    ///         None => { /* update the clock */ },
+    ///     }
+    /// }
+    /// ```
+    pub fn poll_event_timeout(&mut self, timeout: Duration) -> EventResult<Option<Event>> {
+        self.peek_event(timeout)
+    }
+
+    /// Block until an event arrives or `deadline` is reached, whichever is first; `None` means the
+    /// deadline passed.  Unlike a timeout, a deadline doesn't restart on each call, so a loop can
+    /// keep waiting for the same instant in between other work.  If the deadline has already
+    /// passed, this only returns an event that is already queued.
+    pub fn peek_event_until(&mut self, deadline: Instant) -> EventResult<Option<Event>> {
+        let now = Instant::now();
+        let timeout = if deadline > now { deadline - now } else { Duration::from_millis(0) };
+        self.peek_event(timeout)
+    }
+
+    /// Run an event loop, passing each event to `handler` as it arrives and calling it with
+    /// `LoopEvent::Tick` every `tick` in between, until the handler returns `ControlFlow::Break`.
+    /// Errors reading events end the loop and are returned.
+    ///
+    /// Ticks are scheduled at fixed intervals from the start of the loop, however long the
+    /// handler takes; if it falls more than a tick behind, the missed ticks are skipped.
+    ///
+    /// ```no_run
+    /// # use rustbox::{RustBox, Color, Event, Key, LoopEvent, ControlFlow, RB_NORMAL};
+    /// # use std::time::Duration;
+    /// let mut rb = RustBox::init().unwrap();
+    /// let mut frame = 0;
+    /// rb.run_loop(Duration::from_millis(100), |rb, ev| {
+    ///     match ev {
+    ///         LoopEvent::Input(Event::KeyEvent(_, Key::Char('q'))) => return ControlFlow::Break,
+    ///         LoopEvent::Tick => frame += 1,
+    ///         _ => { },
+    ///     }
+    ///     let spinner = ['|', '/', '-', '\\'][frame % 4];
+    ///     rb.print_char(0, 0, RB_NORMAL, Color::Default, Color::Default, spinner);
+    ///     rb.present();
+    ///     ControlFlow::Continue
+    /// }).unwrap();
+    /// ```
+    pub fn run_loop<F>(&mut self, tick: Duration, mut handler: F) -> EventResult<()>
+        where F: FnMut(&mut RustBox, LoopEvent) -> ControlFlow
+    {
+        let mut next_tick = Instant::now() + tick;
+        loop {
+            let now = Instant::now();
+            let ev = if now >= next_tick {
+                next_tick += tick;
+                if next_tick <= now {
+                    next_tick = now + tick;
+                }
+                LoopEvent::Tick
+            } else {
+                match try!(self.peek_event(next_tick - now)) {
+                    Some(ev) => LoopEvent::Input(ev),
+                    None => continue,
+                }
+            };
+            if handler(self, ev) == ControlFlow::Break {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Return the next event if one is already queued, without blocking.  This is `peek_event`
+    /// with a zero timeout.
+    pub fn try_poll_event(&mut self) -> EventResult<Option<Event>> {
+        self.peek_event(Duration::from_millis(0))
+    }
+
+    /// Return every event that is already queued, without blocking.  Useful for batching input
+    /// between frames of an animation.
+    pub fn poll_events_available(&mut self) -> EventResult<Vec<Event>> {
+        let mut events = Vec::new();
+        while let Some(ev) = try!(self.try_poll_event()) {
+            events.push(ev);
+        }
+        Ok(events)
+    }
+
+    /// Decode the Ctrl+letter key codes (0x01 through 0x1A, e.g. `key::CTRL_A`) as
+    /// `KeyEvent(Some(Modifier::Ctrl), Key::Char(letter))` instead of as raw keys.
+    ///
+    /// This is off by default because the terminal sends the same codes for other keys: with it
+    /// on, Tab arrives as Ctrl+i, Enter as Ctrl+m and Backspace (on some terminals) as Ctrl+h.
+    /// Keys that already carry `Modifier::Alt` are left alone.
+    pub fn set_decode_ctrl(&mut self, decode: bool) {
+        self.decode_ctrl = decode;
+    }
+
+    /// Append the output of `log` to the file at `path`, creating it if necessary.
+    pub fn set_log_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let file = try!(OpenOptions::new().create(true).append(true).open(path));
+        self.log_file = Some(file);
+        Ok(())
+    }
+
+    /// Write a line of debugging output without disturbing the screen, as `println!` would.  It
+    /// goes to the file set with `set_log_file` if there is one, or else to stderr if stderr is
+    /// being held (see `InitOptions::buffer_stderr`), to appear once the terminal is restored.
+    /// Otherwise it is discarded.
+    pub fn log(&mut self, msg: &str) {
+        match self.log_file {
+            Some(ref mut file) => {
+                let _ = writeln!(file, "{}", msg);
+            },
+            None if self.stderr.is_some() => {
+                let _ = writeln!(io::stderr(), "{}", msg);
+            },
+            None => { },
+        }
+    }
+
+    /// Log every event subsequently read through this `RustBox` (by `poll_event`, `peek_event`
+    /// and friends) to the file at `path`, replacing its contents.  The log can be fed back with
+    /// `replay_events` or read with `read_event_log`, e.g. to reproduce a bug.
+    ///
+    /// Events are written as they are read.  If writing fails, recording stops.
+    pub fn record_events<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.recorder = Some(try!(record::Recorder::create(path.as_ref())));
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.recorder = None;
+    }
+
+    /// Queue the events logged in `path` by `record_events`.  They are returned by `poll_event`
+    /// etc. before anything further is read from the terminal.
+    pub fn replay_events<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let events = try!(read_event_log(path));
+        self.pending.extend(events);
+        Ok(())
+    }
+
+    /// Reassemble escape sequences that arrive split up.
+    ///
+    /// In `InputMode::Esc`, termbox only recognizes an escape sequence (e.g. for an arrow key) if
+    /// it arrives all at once; over a slow connection it may instead be reported as ESC followed
+    /// by ordinary characters.  With a timeout set, an ESC read through this `RustBox` waits up
+    /// to `timeout` for each following character, and if together they form the sequence for a
+    /// special key, that key is returned instead.  Otherwise the ESC and the characters are
+    /// returned as they are.  termbox itself has no such setting.
+    ///
+    /// The cost is that a real ESC press is reported only after the timeout; something in the
+    /// range of 25 to 100 milliseconds is usually enough.  `None` (the default) turns
+    /// reassembly off.
+    pub fn set_esc_timeout(&mut self, timeout: Option<Duration>) {
+        self.esc_timeout = timeout;
+    }
+
+    /// Turn the terminal's bracketed paste mode on or off.  While it is on, text pasted into the
+    /// terminal is read through this `RustBox` as a single `Event::Paste` rather than as a key
+    /// event per character, so it can't be mistaken for typed commands.  Terminals that don't
+    /// support the mode ignore it, and pasted text arrives as keys as before.
+    ///
+    /// Once the start of a paste has been read, the rest is waited for even by non-blocking
+    /// calls such as `try_poll_event`; if it stops arriving for half a second, the text read so
+    /// far is returned.
+    ///
+    /// The mode is turned off again when the `RustBox` is dropped.  Pastes are not reassembled by
+    /// `EventSource`s.
+    pub fn set_bracketed_paste(&mut self, enable: bool) {
+        self.bracketed_paste = enable;
+        if let Some(ref mut tty) = self.tty {
+            let _ = tty.write(if enable { b"\x1b[?2004h" } else { b"\x1b[?2004l" });
+        }
+    }
+
+    /// Turn the terminal's focus reporting on or off.  While it is on, the terminal gaining or
+    /// losing focus is read through this `RustBox` as `Event::FocusGained` or `Event::FocusLost`.
+    /// Terminals that don't support it ignore it.
+    ///
+    /// Focus reporting is turned off again when the `RustBox` is dropped.  Focus changes are not
+    /// decoded by `EventSource`s.
+    pub fn set_focus_reporting(&mut self, enable: bool) {
+        self.focus_reporting = enable;
+        if let Some(ref mut tty) = self.tty {
+            let _ = tty.write(if enable { b"\x1b[?1004h" } else { b"\x1b[?1004l" });
+        }
+    }
+
+    /// Clear the back buffer whenever a resize event is read through this `RustBox`.
+    ///
+    /// termbox keeps the back buffer's contents when the terminal is resized, so anything drawn
+    /// at the old size stays in place until it is overwritten.  With this on, the next `present`
+    /// after a resize starts from a blank screen.  Resizes read through an `EventSource` are not
+    /// seen here and don't clear the buffer.
+    pub fn set_clear_on_resize(&mut self, clear: bool) {
+        self.clear_on_resize = clear;
+    }
+
+    /// A handle for polling events from another thread.  See `EventSource` for details.
+    pub fn event_source(&self) -> EventSource {
+        event_source::event_source(self.termbox.clone())
+    }
+
+    /// Select the input mode.  Accepts either a single `InputMode` or a combination such as
+    /// `InputMode::Esc | InputMode::Mouse`.
+    pub fn set_input_mode<M: Into<InputModes>>(&mut self, mode: M) {
+        let mode = mode.into();
+        {
+            let _tb = self.lock();
+            unsafe {
+                termbox::tb_select_input_mode(mode.bits());
+            }
+        }
+        // An empty mode leaves the input mode as it is.
+        let motion = mode.contains(input_mode::RB_INPUT_MOUSE_MOTION);
+        if !mode.is_empty() && motion != self.mouse_motion {
+            if let Some(ref mut tty) = self.tty {
+                let _ = tty.write(if motion { b"\x1b[?1003h" } else { b"\x1b[?1003l" });
+            }
+            self.mouse_motion = motion;
+        }
+    }
+
+    /// Select the output mode.  Colors passed to the drawing functions afterwards are encoded
+    /// for the new mode; cells already in the back buffer keep the encoding they were drawn with,
+    /// so redraw everything after switching.
+    pub fn set_output_mode(&mut self, mode: OutputMode) {
+        {
+            let _tb = self.lock();
+            unsafe {
+                termbox::tb_select_output_mode(mode.termbox_mode());
+            }
+        }
+        if mode != OutputMode::Current {
+            self.output_mode = mode;
+            let (fg, bg) = (self.clear_fg, self.clear_bg);
+            self.set_clear_attributes(fg, bg);
         }
     }
 
-    pub fn get_input_mode(&self) {
+    pub fn output_mode(&self) -> OutputMode {
+        self.output_mode
+    }
+
+    /// Whether characters of ambiguous width (such as `§`, `①` and many Greek and Cyrillic
+    /// letters) are drawn two columns wide, as terminals do in CJK locales, rather than one.
+    /// `print`, `print_spans` and `try_print` measure text accordingly; `text_width`,
+    /// `print_wrapped` and `PreparedText` always measure ambiguous characters as narrow.
+    ///
+    /// This is guessed at initialization from `$VTE_CJK_WIDTH` and the locale; terminals can be
+    /// configured either way, so let users override it with `set_ambiguous_width_wide`.
+    pub fn ambiguous_width_is_wide(&self) -> bool {
+        self.ambiguous_wide
+    }
+
+    pub fn set_ambiguous_width_wide(&mut self, wide: bool) {
+        self.ambiguous_wide = wide;
+    }
+
+    fn guess_ambiguous_wide() -> bool {
+        let vte = env::var("VTE_CJK_WIDTH").ok();
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|l| !l.is_empty());
+        detect_ambiguous_wide(vte.as_ref().map(|s| &s[..]), locale.as_ref().map(|s| &s[..]))
+    }
+
+    /// Guess the colors the terminal supports from `$COLORTERM` and `$TERM`.  Terminals that
+    /// don't advertise anything better are assumed to support the 8 basic colors.
+    ///
+    /// ```no_run
+    /// # use rustbox::RustBox;
+    /// let mut rb = RustBox::init().unwrap();
+    /// rb.set_output_mode(RustBox::output_capability().output_mode());
+    /// ```
+    pub fn output_capability() -> OutputCapability {
+        let colorterm = env::var("COLORTERM").ok();
+        let term = env::var("TERM").ok();
+        detect_capability(colorterm.as_ref().map(|s| &s[..]), term.as_ref().map(|s| &s[..]))
+    }
+
+    /// Query the current input mode.  The result can be passed back to `set_input_mode` to
+    /// restore it later.
+    pub fn get_input_mode(&self) -> InputModes {
+        let _tb = self.lock();
+        InputModes::from_bits_truncate(unsafe {
+            termbox::tb_select_input_mode(0 as c_int)
+        })
+    }
+}
+
+/// An iterator over events, returned by `RustBox::events`.
+pub struct EventIterator<'a> {
+    rb: &'a mut RustBox,
+}
+
+impl<'a> Iterator for EventIterator<'a> {
+    type Item = EventResult<Event>;
+
+    fn next(&mut self) -> Option<EventResult<Event>> {
+        Some(self.rb.poll_event())
+    }
+}
+
+impl RustBox {
+    // Lock termbox against concurrent use by event sources.  Hold this only around individual
+    // termbox calls.
+    //
+    // Calling into termbox while it isn't initialized (e.g. while suspended, or after the panic
+    // hook has shut it down) is undefined behaviour, so debug builds check for it here.  Use
+    // `lock_raw` where termbox is initialized or shut down.
+    fn lock(&self) -> MutexGuard<bool> {
+        debug_assert!(running::is_initialized(), "termbox isn't running");
+        self.lock_raw()
+    }
+
+    fn lock_raw(&self) -> MutexGuard<bool> {
+        event_source::lock(&self.termbox)
+    }
+
+    // Print characters paired with their widths; see `print`.
+    fn print_measured<I>(&mut self, x: usize, y: usize, sty: Style, fg: Color, bg: Color,
+                         chars: I) -> usize
+        where I: Iterator<Item=(char, usize)>
+    {
+        let (fg, bg) = self.encode(sty, fg, bg);
+        self.dirty = true;
+        // One lock for the whole string, rather than one per cell, so that an event source
+        // waiting for input can't hold up the drawing between cells.
+        let _tb = self.lock();
+        let (width, height) = unsafe { (dimension(termbox::tb_width()),
+                                        dimension(termbox::tb_height())) };
+        if y >= height {
+            return 0;
+        }
+        text::layout_row(x, width, chars, |col, ch| unsafe {
+            termbox::tb_change_cell(col as c_int, y as c_int, ch as u32, fg.bits(), bg.bits())
+        })
+    }
+
+    // How to measure the number of columns a character is drawn in, taking `ambiguous_wide`
+    // into account.
+    fn width_fn(&self) -> fn(char) -> usize {
+        text::width_fn(self.ambiguous_wide)
+    }
+
+    fn column_width(&self, ch: char) -> usize {
+        (self.width_fn())(ch)
+    }
+
+    fn string_width(&self, s: &str) -> usize {
+        s.chars().map(|ch| self.column_width(ch)).fold(0, |acc, w| acc + w)
+    }
+
+    // Encode a style and a pair of colors for the current output mode, dropping the colors with
+    // `no_color`.
+    fn encode(&self, sty: Style, fg: Color, bg: Color) -> (Style, Style) {
+        if self.no_color {
+            style::encode(sty, Color::Default, Color::Default, self.output_mode)
+        } else {
+            style::encode(sty, fg, bg, self.output_mode)
+        }
+    }
+
+    // Fill a rectangle of the back buffer with `raw`, clipping it to the screen.
+    fn fill_raw(&mut self, x: usize, y: usize, w: usize, h: usize, raw: RawCell) {
+        self.dirty = true;
+        let _tb = self.lock();
         unsafe {
-            termbox::tb_select_input_mode(0 as c_int);
+            let (width, height) = (dimension(termbox::tb_width()), dimension(termbox::tb_height()));
+            if x >= width || y >= height {
+                return;
+            }
+            let cols = cmp::min(w, width - x);
+            let rows = cmp::min(h, height - y);
+            let buffer = termbox::tb_cell_buffer();
+            for row in 0..rows {
+                let dst = buffer.offset(((y + row) * width + x) as isize);
+                for col in 0..cols {
+                    *dst.offset(col as isize) = raw;
+                }
+            }
+        }
+    }
+
+    // Turn the terminal modes termbox doesn't know about (bracketed paste etc.) on or off, if they
+    // are enabled.
+    fn write_terminal_modes(&mut self, on: bool) {
+        if let Some(ref mut tty) = self.tty {
+            let end = if on { "h" } else { "l" };
+            if self.bracketed_paste {
+                let _ = tty.write(format!("\x1b[?2004{}", end).as_bytes());
+            }
+            if self.focus_reporting {
+                let _ = tty.write(format!("\x1b[?1004{}", end).as_bytes());
+            }
+            if self.mouse_motion {
+                let _ = tty.write(format!("\x1b[?1003{}", end).as_bytes());
+            }
+            // termbox switches the terminal back to keypad mode itself when it shuts down.
+            if self.normalize_keys && on {
+                let _ = tty.write(b"\x1b[?1l\x1b>");
+            }
+            // termbox always switches to the alternate screen; switch straight back.
+            if !self.alternate_screen && on {
+                let _ = tty.write(b"\x1b[?1049l");
+            }
+        }
+    }
+
+    // Log an event if recording.
+    fn record(&mut self, ev: &Event) {
+        let failed = match self.recorder {
+            Some(ref mut recorder) => recorder.record(ev).is_err(),
+            None => false,
+        };
+        if failed {
+            self.recorder = None;
         }
     }
+
+    // Turn an event just read from termbox into the event to report; see `reassemble`.
+    fn reassemble(&mut self, ev: Event) -> EventResult<Event> {
+        let settings = reassemble::Settings {
+            esc_timeout: self.esc_timeout,
+            normalize_keys: self.normalize_keys,
+            bracketed_paste: self.bracketed_paste,
+            focus_reporting: self.focus_reporting,
+        };
+        reassemble::reassemble(self, settings, ev)
+    }
+
+    // Unpack a raw event, updating any state that tracks it.
+    fn decode_event(&mut self, raw: RawEvent) -> EventResult<Event> {
+        let ev = try!(unpack_event(raw.0));
+        Ok(match ev {
+            Event::ResizeEvent { .. } => {
+                // Report the size of termbox's buffers, which drawing is clipped to, so that the
+                // event always agrees with `width`, `height` and `size`.
+                let (width, height) = (self.width(), self.height());
+                self.size = (width, height);
+                if self.clear_on_resize {
+                    self.clear();
+                }
+                Event::ResizeEvent { width, height }
+            },
+            Event::KeyEvent(None, Key::Key(code @ 0x01...0x1a)) if self.decode_ctrl => {
+                Event::KeyEvent(Some(Modifier::Ctrl), Key::Char((b'a' + code as u8 - 1) as char))
+            },
+            ev => ev,
+        })
+    }
+}
+
+impl EventQueue for RustBox {
+    fn read(&mut self, timeout: Option<Duration>) -> EventResult<Option<Event>> {
+        let raw = match timeout {
+            Some(timeout) => try!(self.peek_event_raw(timeout)),
+            None => Some(try!(self.poll_event_raw())),
+        };
+        match raw {
+            Some(raw) => self.decode_event(raw).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn pending(&mut self) -> &mut VecDeque<Event> {
+        &mut self.pending
+    }
 }
 
 impl Drop for RustBox {
@@ -299,8 +2414,258 @@ impl Drop for RustBox {
         // Since only one instance of the RustBox is ever accessible, we should not
         // need to do this atomically.
         // Note: we should definitely have RUSTBOX_RUNNING = true here.
-        unsafe {
-            termbox::tb_shutdown();
+        self.write_terminal_modes(false);
+        // termbox clears the screen when it shuts down, so the frame has to be saved first.  If
+        // termbox is suspended, or has already been shut down by the panic hook, there is no
+        // frame to save.
+        let frame = if self.alternate_screen || self.suspended.is_some() ||
+                       !running::is_initialized() {
+            None
+        } else {
+            Some(frame_text(&self.snapshot(), self.output_mode, self.width_fn()))
+        };
+        {
+            let mut open = self.lock_raw();
+            *open = false;
+            running::shutdown();
+        }
+        // The frame goes back where it was drawn, from the top of the (now cleared) screen.
+        if let (Some(text), Some(tty)) = (frame, self.tty.as_mut()) {
+            let _ = tty.write(format!("\x1b[H{}", text).as_bytes());
+        }
+    }
+}
+
+/// The terminal output that redraws `frame` from the cursor onwards, with the colors and
+/// attributes termbox draws its cells with in `mode`.  Each row ends with a newline; blanks in the
+/// default colors at the ends of rows, and rows left empty at the bottom, are dropped.  `width`
+/// measures characters so that the blank `print` leaves after each wide character is skipped.
+fn frame_text(frame: &CellBuffer, mode: OutputMode, width: fn(char) -> usize) -> String {
+    let mut rows = Vec::new();
+    for y in 0..frame.height() {
+        let row = frame.row(y);
+        let len = row.iter().rposition(|c| *c != Cell::default()).map_or(0, |i| i + 1);
+        let mut text = String::new();
+        let mut attrs = None;
+        let mut skip = false;
+        for c in &row[..len] {
+            if !skip {
+                if attrs != Some((c.fg, c.bg)) {
+                    text.push_str(&sgr(c.fg, c.bg, mode));
+                    attrs = Some((c.fg, c.bg));
+                }
+                text.push(c.ch);
+            }
+            skip = !skip && width(c.ch) == 2;
+        }
+        if attrs.is_some() {
+            text.push_str("\x1b[0m");
+        }
+        rows.push(text);
+    }
+    while rows.last().map_or(false, |r| r.is_empty()) {
+        rows.pop();
+    }
+    rows.iter().fold(String::new(), |acc, row| acc + row + "\r\n")
+}
+
+/// The SGR sequence that selects the given termbox foreground and background attributes, as
+/// termbox itself would draw them in `mode`.
+fn sgr(fg: Style, bg: Style, mode: OutputMode) -> String {
+    let mut params = vec![String::from("0")];
+    if fg.contains(RB_BOLD) {
+        params.push(String::from("1"));
+    }
+    // termbox draws a bold background as blinking.
+    if bg.contains(RB_BOLD) {
+        params.push(String::from("5"));
+    }
+    if fg.contains(RB_UNDERLINE) {
+        params.push(String::from("4"));
+    }
+    if fg.contains(RB_REVERSE) || bg.contains(RB_REVERSE) {
+        params.push(String::from("7"));
+    }
+    for &(attr, normal, indexed) in &[(fg, 30, 38), (bg, 40, 48)] {
+        let code = attr.bits() & style::TB_256_COLOR.bits();
+        match mode {
+            OutputMode::Output256 if code != 0 => params.push(format!("{};5;{}", indexed, code)),
+            OutputMode::Output216 => params.push(format!("{};5;{}", indexed, code + 16)),
+            OutputMode::Grayscale => params.push(format!("{};5;{}", indexed, code + 232)),
+            OutputMode::Output256 => { },
+            _ => {
+                let code = attr.bits() & style::TB_NORMAL_COLOR.bits();
+                if code != 0 {
+                    params.push((normal + code - 1).to_string());
+                }
+            },
         }
     }
+    format!("\x1b[{}m", params.join(";"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_ambiguous_wide, frame_text, rgb_to_256, running, unpack_event, InitError,
+                InitOptions, RustBox};
+    use super::{key, Cell, CellBuffer, Color, Event, Key, Modifier, OutputMode, NIL_RAW_EVENT};
+    use super::{RB_BLINK, RB_BOLD, RB_NORMAL, RB_UNDERLINE};
+    use text::char_width;
+    use termbox;
+
+    // Both checks share the global running flag, so they are made in one test rather than two that
+    // could run concurrently.
+    #[test]
+    fn failed_init_releases_lock() {
+        {
+            let guard = running::run().expect("nothing else is running");
+            assert!(running());
+            assert!(running::run().is_none());
+            drop(guard);
+        }
+        assert!(!running());
+
+        // A terminal that can't be opened fails in `init_file` (`tb_init_file`), after the lock has
+        // been taken.
+        for _ in 0..2 {
+            match RustBox::init_file("/nonexistent/tty", InitOptions::default()) {
+                Err(InitError::FailedToOpenTty) => { },
+                Err(e) => panic!("unexpected error: {}", e),
+                Ok(_) => panic!("opened a nonexistent terminal"),
+            }
+            assert!(!running());
+            assert!(!running::is_initialized());
+        }
+    }
+
+    #[test]
+    fn alt_applies_to_every_key() {
+        let keys = [(Key::Char('x'), 0, 'x' as u32), (key::ARROW_LEFT, 0xFFFF - 20, 0),
+                    (key::F5, 0xFFFF - 4, 0)];
+        for &(k, code, ch) in &keys {
+            let raw = termbox::RawEvent { etype: 1, emod: 1, key: code, ch, ..NIL_RAW_EVENT };
+            match unpack_event(raw) {
+                Ok(ev) => assert_eq!(ev, Event::KeyEvent(Some(Modifier::Alt), k)),
+                Err(e) => panic!("{:?} failed to decode: {}", k, e),
+            }
+            let raw = termbox::RawEvent { emod: 0, ..raw };
+            assert_eq!(unpack_event(raw).ok(), Some(Event::KeyEvent(None, k)));
+        }
+    }
+
+    #[test]
+    fn color_from_hex() {
+        assert_eq!(Color::from_hex("#ff8700"), Ok(Color::Rgb(0xff, 0x87, 0x00)));
+        assert_eq!(Color::from_hex("#FF8700"), Ok(Color::Rgb(0xff, 0x87, 0x00)));
+        assert_eq!(Color::from_hex("#f80"), Ok(Color::Rgb(0xff, 0x88, 0x00)));
+        assert_eq!(Color::from_hex("#F80"), Ok(Color::Rgb(0xff, 0x88, 0x00)));
+        assert_eq!(Color::from_hex("#000000"), Ok(Color::Rgb(0, 0, 0)));
+        for s in &["ff8800", "#12345", "#1234567", "#gg0000", "#", "", "#ff 800"] {
+            assert!(Color::from_hex(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn color_from_tuple() {
+        assert_eq!(Color::from((0x12, 0x34, 0x56)), Color::Rgb(0x12, 0x34, 0x56));
+        let c: Color = (0, 0, 0).into();
+        assert_eq!(c, Color::Rgb(0, 0, 0));
+    }
+
+    #[test]
+    fn rgb_to_256_picks_nearest_entry() {
+        let table = [
+            ((0x00, 0x00, 0x00), 16),
+            ((0xff, 0xff, 0xff), 231),
+            ((0xff, 0x87, 0x00), 208),
+            ((0x5f, 0x87, 0xaf), 67),
+            // Greys are closer to the grey ramp than to the cube.
+            ((0x08, 0x08, 0x08), 232),
+            ((0x80, 0x80, 0x80), 244),
+            ((0xee, 0xee, 0xee), 255),
+        ];
+        for &((r, g, b), index) in &table {
+            assert_eq!(rgb_to_256(r, g, b), index, "{:?}", (r, g, b));
+        }
+    }
+
+    #[test]
+    fn color_codes_per_mode() {
+        let table = [
+            (OutputMode::Normal, Color::Default, 0x00),
+            (OutputMode::Normal, Color::Black, 0x01),
+            (OutputMode::Normal, Color::White, 0x08),
+            (OutputMode::Normal, Color::Byte(9), 0x02),
+            (OutputMode::Normal, Color::Byte(200), 0x00),
+            (OutputMode::Normal, Color::Rgb(0xff, 0x00, 0x00), 0x02),
+            (OutputMode::Normal, Color::Rgb(0xff, 0xff, 0xff), 0x08),
+            (OutputMode::Output256, Color::Default, 0x00),
+            // Palette entry 0 would be drawn in the default color, so black comes from the cube.
+            (OutputMode::Output256, Color::Black, 0x10),
+            (OutputMode::Output256, Color::Byte(0), 0x10),
+            (OutputMode::Output256, Color::Red, 0x01),
+            (OutputMode::Output256, Color::White, 0x07),
+            (OutputMode::Output256, Color::Byte(208), 208),
+            (OutputMode::Output256, Color::Rgb(0xff, 0x87, 0x00), 208),
+            (OutputMode::Output256, Color::Rgb(0x00, 0x00, 0x00), 0x10),
+            (OutputMode::Output216, Color::Default, 0),
+            (OutputMode::Output216, Color::Byte(16), 0),
+            (OutputMode::Output216, Color::Byte(208), 192),
+            (OutputMode::Output216, Color::Byte(231), 215),
+            (OutputMode::Output216, Color::Red, 144),
+            (OutputMode::Output216, Color::Rgb(0xff, 0xff, 0xff), 215),
+            (OutputMode::Grayscale, Color::Default, 0),
+            (OutputMode::Grayscale, Color::Black, 0),
+            (OutputMode::Grayscale, Color::Byte(232), 0),
+            (OutputMode::Grayscale, Color::Byte(255), 23),
+            (OutputMode::Grayscale, Color::White, 22),
+            (OutputMode::Grayscale, Color::Rgb(0x80, 0x80, 0x80), 12),
+            (OutputMode::Monochrome, Color::Red, 0),
+            (OutputMode::Monochrome, Color::Byte(208), 0),
+            (OutputMode::Monochrome, Color::Rgb(0xff, 0xff, 0xff), 0),
+        ];
+        for &(mode, color, code) in &table {
+            assert_eq!(color.code(mode), code, "{:?} in {:?}", color, mode);
+        }
+    }
+
+    #[test]
+    fn saved_frame_keeps_colors_and_attributes() {
+        let mut frame = CellBuffer::new(6, 3, Cell::default());
+        frame.set(0, 0, Cell::from('a'));
+        frame.set(1, 0, Cell::styled('b', RB_BOLD, Color::Red, Color::Default, OutputMode::Normal));
+        frame.set(2, 0, Cell::styled('c', RB_BOLD, Color::Red, Color::Default, OutputMode::Normal));
+        frame.set(0, 1, Cell::styled('日', RB_NORMAL, Color::Default, Color::Blue,
+                                     OutputMode::Normal));
+        frame.set(1, 1, Cell::styled(' ', RB_NORMAL, Color::Default, Color::Blue,
+                                     OutputMode::Normal));
+        assert_eq!(frame_text(&frame, OutputMode::Normal, char_width),
+                   "\x1b[0ma\x1b[0;1;31mbc\x1b[0m\r\n\x1b[0;44m日\x1b[0m\r\n");
+    }
+
+    #[test]
+    fn saved_frame_in_256_colors() {
+        let mut frame = CellBuffer::new(3, 1, Cell::default());
+        let mode = OutputMode::Output256;
+        frame.set(0, 0, Cell::styled('x', RB_UNDERLINE, Color::Byte(208), Color::Black, mode));
+        frame.set(1, 0, Cell::styled('y', RB_BLINK, Color::Default, Color::Default, mode));
+        assert_eq!(frame_text(&frame, mode, char_width),
+                   "\x1b[0;4;38;5;208;48;5;16mx\x1b[0;5my\x1b[0m\r\n");
+    }
+
+    #[test]
+    fn blank_frame_saves_nothing() {
+        let frame = CellBuffer::new(4, 2, Cell::default());
+        assert_eq!(frame_text(&frame, OutputMode::Normal, char_width), "");
+    }
+
+    #[test]
+    fn ambiguous_width_from_locale() {
+        assert!(detect_ambiguous_wide(None, Some("ja_JP.UTF-8")));
+        assert!(detect_ambiguous_wide(None, Some("zh_CN.UTF-8")));
+        assert!(!detect_ambiguous_wide(None, Some("en_US.UTF-8")));
+        assert!(!detect_ambiguous_wide(None, None));
+        assert!(detect_ambiguous_wide(Some("wide"), Some("en_US.UTF-8")));
+        assert!(!detect_ambiguous_wide(Some("narrow"), Some("ko_KR.UTF-8")));
+    }
 }
@@ -0,0 +1,257 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeSeq, Serializer};
+
+use super::{record, Color, Event, Key, Modifier, Style};
+use super::{RB_BOLD, RB_UNDERLINE, RB_REVERSE, RB_BLINK, RB_NORMAL};
+
+// Every type is serialized as a human-editable string (or, for `Style`, a list of strings) so
+// that keymaps and themes can be written by hand:
+//
+//     Key:      "q", "C-a", "F5", "Enter"        (the notation of `Key`'s `Display` impl)
+//     Modifier: "Alt", "Ctrl"
+//     Color:    "Red", "Default", "208", "#ff8700"
+//     Style:    ["bold", "underline"]
+//     Event:    "key alt Left", "resize 80 24"   (a line of an event log)
+//
+// Names are matched case-insensitively when deserializing.
+
+const STYLE_NAMES: &[(Style, &str)] = &[
+    (RB_BOLD, "bold"),
+    (RB_UNDERLINE, "underline"),
+    (RB_REVERSE, "reverse"),
+    (RB_BLINK, "blink"),
+];
+
+/// Deserializes a `T` from a string with the given parse function.
+struct StrVisitor<T> {
+    expecting: &'static str,
+    parse: fn(&str) -> Option<T>,
+    marker: PhantomData<T>,
+}
+
+impl<'de, T> Visitor<'de> for StrVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.expecting)
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<T, E> {
+        (self.parse)(s).ok_or_else(|| E::invalid_value(de::Unexpected::Str(s), &self))
+    }
+}
+
+fn deserialize_str<'de, D, T>(deserializer: D, expecting: &'static str,
+                              parse: fn(&str) -> Option<T>) -> Result<T, D::Error>
+    where D: Deserializer<'de>
+{
+    deserializer.deserialize_str(StrVisitor {
+        expecting,
+        parse,
+        marker: PhantomData,
+    })
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        deserialize_str(deserializer, "a key such as \"q\", \"C-a\" or \"F5\"", |s| s.parse().ok())
+    }
+}
+
+impl Serialize for Modifier {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match *self {
+            Modifier::Alt => "Alt",
+            Modifier::Ctrl => "Ctrl",
+        })
+    }
+}
+
+fn parse_modifier(s: &str) -> Option<Modifier> {
+    if s.eq_ignore_ascii_case("Alt") {
+        Some(Modifier::Alt)
+    } else if s.eq_ignore_ascii_case("Ctrl") {
+        Some(Modifier::Ctrl)
+    } else {
+        None
+    }
+}
+
+impl<'de> Deserialize<'de> for Modifier {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Modifier, D::Error> {
+        deserialize_str(deserializer, "\"Alt\" or \"Ctrl\"", parse_modifier)
+    }
+}
+
+fn format_color(color: Color) -> String {
+    match color {
+        Color::Byte(b) => b.to_string(),
+        Color::Rgb(r, g, b) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+        c => c.name().to_string(),
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_color(*self))
+    }
+}
+
+fn parse_color(s: &str) -> Option<Color> {
+    let mut named = Some(Color::Default).into_iter().chain(Color::all());
+    if let Some(color) = named.find(|c| c.name().eq_ignore_ascii_case(s)) {
+        return Some(color);
+    }
+    if s.starts_with('#') {
+        return Color::from_hex(s).ok();
+    }
+    s.parse().ok().map(Color::Byte)
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        deserialize_str(deserializer, "a color name, a palette index or \"#rrggbb\"", parse_color)
+    }
+}
+
+fn style_names(style: Style) -> Vec<&'static str> {
+    STYLE_NAMES.iter()
+        .filter(|&&(flag, _)| style.contains(flag))
+        .map(|&(_, name)| name)
+        .collect()
+}
+
+/// Only the attribute flags are serialized; any color bits in the style are dropped.
+impl Serialize for Style {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let names = style_names(*self);
+        let mut seq = try!(serializer.serialize_seq(Some(names.len())));
+        for name in names {
+            try!(seq.serialize_element(name));
+        }
+        seq.end()
+    }
+}
+
+struct StyleVisitor;
+
+impl<'de> Visitor<'de> for StyleVisitor {
+    type Value = Style;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("a list of \"bold\", \"underline\", \"reverse\" and \"blink\"")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Style, A::Error> {
+        let mut style = RB_NORMAL;
+        while let Some(name) = try!(seq.next_element::<String>()) {
+            match STYLE_NAMES.iter().find(|&&(_, n)| n.eq_ignore_ascii_case(&name)) {
+                Some(&(flag, _)) => style = style | flag,
+                None => return Err(de::Error::invalid_value(de::Unexpected::Str(&name), &self)),
+            }
+        }
+        Ok(style)
+    }
+}
+
+impl<'de> Deserialize<'de> for Style {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Style, D::Error> {
+        deserializer.deserialize_seq(StyleVisitor)
+    }
+}
+
+impl Serialize for Event {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&record::encode(self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Event {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Event, D::Error> {
+        deserialize_str(deserializer, "an event such as \"key - q\" or \"resize 80 24\"",
+                        record::decode)
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use serde::de::value::{Error, SeqDeserializer, StrDeserializer};
+    use serde::de::{Deserialize, IntoDeserializer};
+
+    use super::{format_color, style_names};
+    use {key, record, Color, Event, Key, Modifier, Style};
+    use {RB_BOLD, RB_UNDERLINE, RB_REVERSE, RB_BLINK, RB_NORMAL};
+
+    fn from_str<'de, T: Deserialize<'de>>(s: &'de str) -> Result<T, Error> {
+        let d: StrDeserializer<Error> = s.into_deserializer();
+        T::deserialize(d)
+    }
+
+    fn style_from(names: &[&'static str]) -> Result<Style, Error> {
+        Style::deserialize(SeqDeserializer::<_, Error>::new(names.iter().cloned()))
+    }
+
+    #[test]
+    fn color_round_trips() {
+        let colors = [Color::Default, Color::Red, Color::White, Color::Byte(0), Color::Byte(208),
+                      Color::Rgb(0xff, 0x87, 0x00), Color::Rgb(0, 0, 0)];
+        for &c in &colors {
+            assert_eq!(from_str::<Color>(&format_color(c)).ok(), Some(c), "{:?}", c);
+        }
+        assert_eq!(format_color(Color::Rgb(0xff, 0x87, 0x00)), "#ff8700");
+        assert_eq!(format_color(Color::Byte(208)), "208");
+        assert_eq!(from_str::<Color>("red").ok(), Some(Color::Red));
+        assert_eq!(from_str::<Color>("#FF8700").ok(), Some(Color::Rgb(0xff, 0x87, 0x00)));
+    }
+
+    #[test]
+    fn color_rejects_malformed() {
+        for s in &["", "Purple", "256", "-1", "#12345", "#gg0000", "ff8800"] {
+            assert!(from_str::<Color>(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn style_round_trips() {
+        let styles = [RB_NORMAL, RB_BOLD, RB_UNDERLINE | RB_REVERSE,
+                      RB_BOLD | RB_UNDERLINE | RB_REVERSE | RB_BLINK];
+        for &sty in &styles {
+            assert_eq!(style_from(&style_names(sty)).ok(), Some(sty), "{:?}", sty);
+        }
+        assert_eq!(style_names(RB_BOLD | RB_BLINK), vec!["bold", "blink"]);
+        assert_eq!(style_from(&["Bold", "UNDERLINE"]).ok(), Some(RB_BOLD | RB_UNDERLINE));
+        assert!(style_from(&["bold", "italic"]).is_err());
+    }
+
+    #[test]
+    fn key_and_modifier_from_notation() {
+        assert_eq!(from_str::<Key>("q").ok(), Some(Key::Char('q')));
+        assert_eq!(from_str::<Key>("C-a").ok(), Some(key::CTRL_A));
+        assert_eq!(from_str::<Key>("F5").ok(), Some(key::F5));
+        assert!(from_str::<Key>("bogus").is_err());
+        assert_eq!(from_str::<Modifier>("alt").ok(), Some(Modifier::Alt));
+        assert_eq!(from_str::<Modifier>("Ctrl").ok(), Some(Modifier::Ctrl));
+        assert!(from_str::<Modifier>("Shift").is_err());
+    }
+
+    #[test]
+    fn event_round_trips() {
+        let events = [Event::KeyEvent(Some(Modifier::Alt), key::ARROW_LEFT),
+                      Event::ResizeEvent { width: 80, height: 24 },
+                      Event::Paste(String::from("two words"))];
+        for ev in &events {
+            let line = record::encode(ev);
+            assert_eq!(from_str::<Event>(&line).ok().as_ref(), Some(ev), "{:?}", line);
+        }
+        assert!(from_str::<Event>("key sideways q").is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use std::io::{self, Write};
+use libc::{self, c_int, c_void};
+
+/// Redirects stderr into a pipe for as long as it is alive.  When dropped, stderr is restored and
+/// everything that was captured is written to it.
+///
+/// The write end of the pipe is nonblocking: once it fills up, further writes to stderr fail
+/// (rather than block) until the hold is released.
+pub struct Hold {
+    saved: c_int,
+    pipe: c_int,
+}
+
+impl Hold {
+    pub fn stderr() -> io::Result<Hold> {
+        unsafe {
+            let mut fds = [0 as c_int; 2];
+            if libc::pipe(fds.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let (read_end, write_end) = (fds[0], fds[1]);
+
+            let flags = libc::fcntl(write_end, libc::F_GETFL);
+            libc::fcntl(write_end, libc::F_SETFL, flags | libc::O_NONBLOCK);
+
+            let saved = libc::dup(libc::STDERR_FILENO);
+            if saved < 0 || libc::dup2(write_end, libc::STDERR_FILENO) < 0 {
+                let err = io::Error::last_os_error();
+                if saved >= 0 {
+                    libc::close(saved);
+                }
+                libc::close(read_end);
+                libc::close(write_end);
+                return Err(err);
+            }
+            // stderr now holds the only reference to the write end.
+            libc::close(write_end);
+
+            Ok(Hold { saved, pipe: read_end })
+        }
+    }
+}
+
+impl Drop for Hold {
+    fn drop(&mut self) {
+        unsafe {
+            // Restoring stderr closes the last write end of the pipe, so the reads below will
+            // hit EOF once everything has been drained.
+            libc::dup2(self.saved, libc::STDERR_FILENO);
+            libc::close(self.saved);
+
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = libc::read(self.pipe, buf.as_mut_ptr() as *mut c_void, buf.len());
+                if n <= 0 {
+                    break;
+                }
+                let _ = io::stderr().write_all(&buf[..n as usize]);
+            }
+            libc::close(self.pipe);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::char;
+#[cfg(feature = "test-backend")]
+use std::cmp;
+
+use super::{Cell, Color, OutputMode, RustBox, Style};
+use super::style;
+use text;
+
+#[cfg(feature = "test-backend")]
+use super::CellBuffer;
+#[cfg(feature = "test-backend")]
+use cell;
+
+/// Something that can be drawn on: the terminal (`RustBox`) or, with the `test-backend` feature,
+/// an in-memory `TestBackend`.  Write drawing code against this trait to be able to test it
+/// without a terminal, or to draw to other kinds of surface.
+///
+/// `RustBox` keeps its inherent methods of the same names, so code that doesn't care about other
+/// surfaces is unaffected.
+///
+/// ```no_run
+/// use rustbox::{RustBox, Surface, Color, RB_NORMAL};
+///
+/// fn status_line<S: Surface>(s: &mut S, msg: &str) {
+///     let y = s.height() - 1;
+///     s.print(0, y, RB_NORMAL, Color::Black, Color::White, msg);
+/// }
+///
+/// let mut rb = RustBox::init().unwrap();
+/// status_line(&mut rb, "ready");
+/// ```
+pub trait Surface {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+
+    /// The output mode colors are encoded for.
+    fn output_mode(&self) -> OutputMode;
+
+    /// Whether characters of ambiguous width are drawn two columns wide.  See
+    /// `RustBox::ambiguous_width_is_wide`.
+    fn ambiguous_width_is_wide(&self) -> bool {
+        false
+    }
+
+    /// Clear the back buffer.
+    fn clear(&mut self);
+
+    /// Show the back buffer.
+    fn present(&mut self);
+
+    /// Move the cursor to (x, y) and show it.
+    fn set_cursor(&mut self, x: usize, y: usize);
+
+    fn hide_cursor(&mut self);
+
+    /// Write a cell to the back buffer.  Writes outside of the surface are ignored.
+    fn set_cell(&mut self, x: usize, y: usize, cell: Cell);
+
+    /// Write a cell to the back buffer from raw termbox attributes.  See `RustBox::change_cell`.
+    unsafe fn change_cell(&mut self, x: usize, y: usize, ch: u32, fg: u16, bg: u16) {
+        let ch = char::from_u32(ch).unwrap_or('\u{FFFD}');
+        self.set_cell(x, y, Cell::new(ch, Style::from_bits_truncate(fg),
+                                      Style::from_bits_truncate(bg)));
+    }
+
+    /// Write a single character at (x, y).
+    fn print_char(&mut self, x: usize, y: usize, sty: Style, fg: Color, bg: Color, ch: char) {
+        let (fg, bg) = style::encode(sty, fg, bg, self.output_mode());
+        self.set_cell(x, y, Cell::new(ch, fg, bg));
+    }
+
+    /// Print a string starting at (x, y), clipping it at the edge of the surface.  See
+    /// `RustBox::print`.
+    fn print(&mut self, x: usize, y: usize, sty: Style, fg: Color, bg: Color, s: &str) -> usize {
+        let (fg, bg) = style::encode(sty, fg, bg, self.output_mode());
+        if y >= self.height() {
+            return 0;
+        }
+        let width = self.width();
+        let char_width = text::width_fn(self.ambiguous_width_is_wide());
+        text::layout_row(x, width, s.chars().map(|ch| (ch, char_width(ch))),
+                         |col, ch| self.set_cell(col, y, Cell::new(ch, fg, bg)))
+    }
+}
+
+impl Surface for RustBox {
+    fn width(&self) -> usize {
+        RustBox::width(self)
+    }
+
+    fn height(&self) -> usize {
+        RustBox::height(self)
+    }
+
+    fn output_mode(&self) -> OutputMode {
+        RustBox::output_mode(self)
+    }
+
+    fn ambiguous_width_is_wide(&self) -> bool {
+        RustBox::ambiguous_width_is_wide(self)
+    }
+
+    fn clear(&mut self) {
+        RustBox::clear(self)
+    }
+
+    fn present(&mut self) {
+        RustBox::present(self)
+    }
+
+    fn set_cursor(&mut self, x: usize, y: usize) {
+        RustBox::set_cursor(self, x, y)
+    }
+
+    fn hide_cursor(&mut self) {
+        RustBox::hide_cursor(self)
+    }
+
+    fn set_cell(&mut self, x: usize, y: usize, cell: Cell) {
+        RustBox::set_cell(self, x, y, cell)
+    }
+
+    unsafe fn change_cell(&mut self, x: usize, y: usize, ch: u32, fg: u16, bg: u16) {
+        RustBox::change_cell(self, x, y, ch, fg, bg)
+    }
+
+    fn print_char(&mut self, x: usize, y: usize, sty: Style, fg: Color, bg: Color, ch: char) {
+        RustBox::print_char(self, x, y, sty, fg, bg, ch)
+    }
+
+    fn print(&mut self, x: usize, y: usize, sty: Style, fg: Color, bg: Color, s: &str) -> usize {
+        RustBox::print(self, x, y, sty, fg, bg, s)
+    }
+}
+
+/// An in-memory `Surface` for testing drawing code.  It doesn't touch termbox or the terminal, so
+/// any number of them can exist at once, alongside a `RustBox` or not.
+///
+/// Drawing goes to a back buffer; `present` copies it to the front buffer, which is what the
+/// terminal would be showing.
+///
+/// ```
+/// use rustbox::{Surface, TestBackend, Color, RB_NORMAL};
+///
+/// let mut tb = TestBackend::new(10, 2);
+/// tb.print(0, 0, RB_NORMAL, Color::Default, Color::Default, "hello");
+/// tb.present();
+/// assert_eq!(tb.row_text(0), "hello");
+/// ```
+#[cfg(feature = "test-backend")]
+pub struct TestBackend {
+    back: CellBuffer,
+    front: CellBuffer,
+    output_mode: OutputMode,
+    ambiguous_wide: bool,
+    cursor: Option<(usize, usize)>,
+}
+
+#[cfg(feature = "test-backend")]
+impl TestBackend {
+    /// Create a blank `width` by `height` surface in `OutputMode::Normal`.
+    pub fn new(width: usize, height: usize) -> TestBackend {
+        TestBackend {
+            back: CellBuffer::new(width, height, Cell::default()),
+            front: CellBuffer::new(width, height, Cell::default()),
+            output_mode: OutputMode::Normal,
+            ambiguous_wide: false,
+            cursor: None,
+        }
+    }
+
+    pub fn set_output_mode(&mut self, mode: OutputMode) {
+        if mode != OutputMode::Current {
+            self.output_mode = mode;
+        }
+    }
+
+    /// Draw characters of ambiguous width two columns wide, as `RustBox::set_ambiguous_width_wide`
+    /// does.  Off by default.
+    pub fn set_ambiguous_width_wide(&mut self, wide: bool) {
+        self.ambiguous_wide = wide;
+    }
+
+    /// The back buffer, as drawn since the last `present`.
+    pub fn back_buffer(&self) -> &CellBuffer {
+        &self.back
+    }
+
+    /// The front buffer, as of the last `present`.
+    pub fn buffer(&self) -> &CellBuffer {
+        &self.front
+    }
+
+    /// The position of the cursor, or `None` if it is hidden.
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        self.cursor
+    }
+
+    /// The text of row `y` of the front buffer, as `RustBox::row_text` reports it: trailing
+    /// blanks are trimmed, the blank after each wide character is skipped and rows outside of
+    /// the surface are empty.
+    pub fn row_text(&self, y: usize) -> String {
+        if y >= self.front.height() {
+            return String::new();
+        }
+        cell::row_text(self.front.row(y).iter().map(|c| c.ch), text::width_fn(self.ambiguous_wide))
+    }
+}
+
+#[cfg(feature = "test-backend")]
+impl Surface for TestBackend {
+    fn width(&self) -> usize {
+        self.back.width()
+    }
+
+    fn height(&self) -> usize {
+        self.back.height()
+    }
+
+    fn output_mode(&self) -> OutputMode {
+        self.output_mode
+    }
+
+    fn ambiguous_width_is_wide(&self) -> bool {
+        self.ambiguous_wide
+    }
+
+    fn clear(&mut self) {
+        self.back = CellBuffer::new(self.back.width(), self.back.height(), Cell::default());
+    }
+
+    fn present(&mut self) {
+        self.front = self.back.clone();
+    }
+
+    /// The cursor is clamped to the surface, as `RustBox::set_cursor` clamps it to the screen.
+    fn set_cursor(&mut self, x: usize, y: usize) {
+        let x = cmp::min(x, self.width().saturating_sub(1));
+        let y = cmp::min(y, self.height().saturating_sub(1));
+        self.cursor = Some((x, y));
+    }
+
+    fn hide_cursor(&mut self) {
+        self.cursor = None;
+    }
+
+    fn set_cell(&mut self, x: usize, y: usize, cell: Cell) {
+        self.back.set(x, y, cell);
+    }
+}
+
+#[cfg(all(test, feature = "test-backend"))]
+mod tests {
+    use super::{Surface, TestBackend};
+    use {Color, RB_NORMAL};
+
+    #[test]
+    fn row_text_matches_rustbox() {
+        let mut tb = TestBackend::new(8, 2);
+        tb.print(0, 0, RB_NORMAL, Color::Default, Color::Default, "a日本b");
+        tb.present();
+        assert_eq!(tb.row_text(0), "a日本b");
+        assert_eq!(tb.row_text(1), "");
+        assert_eq!(tb.row_text(2), "");
+    }
+
+    #[test]
+    fn ambiguous_width_is_measured_as_set() {
+        let mut tb = TestBackend::new(4, 1);
+        assert_eq!(tb.print(0, 0, RB_NORMAL, Color::Default, Color::Default, "±±±±±"), 4);
+        tb.set_ambiguous_width_wide(true);
+        assert_eq!(tb.print(0, 0, RB_NORMAL, Color::Default, Color::Default, "±±±"), 4);
+        tb.present();
+        assert_eq!(tb.row_text(0), "±±");
+        assert_eq!(tb.buffer().get(1, 0).map(|c| c.ch), Some(' '));
+    }
+
+    #[test]
+    fn cursor_is_clamped() {
+        let mut tb = TestBackend::new(4, 2);
+        tb.set_cursor(9, 9);
+        assert_eq!(tb.cursor(), Some((3, 1)));
+        tb.set_cursor(1, 0);
+        assert_eq!(tb.cursor(), Some((1, 0)));
+    }
+}
@@ -0,0 +1,210 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+/// The number of terminal columns occupied by a character.
+///
+/// Wide (e.g. CJK) characters occupy two columns.  Zero-width and control characters occupy none;
+/// termbox has no way to combine them with the preceding cell so they are not drawn.
+pub fn char_width(ch: char) -> usize {
+    UnicodeWidthChar::width(ch).unwrap_or(0)
+}
+
+/// The number of terminal columns occupied by a character on a terminal that draws characters of
+/// ambiguous width two columns wide.
+pub fn char_width_cjk(ch: char) -> usize {
+    UnicodeWidthChar::width_cjk(ch).unwrap_or(0)
+}
+
+/// `char_width_cjk` if ambiguous-width characters are drawn two columns wide, otherwise
+/// `char_width`.
+pub fn width_fn(ambiguous_wide: bool) -> fn(char) -> usize {
+    if ambiguous_wide { char_width_cjk } else { char_width }
+}
+
+/// Lay out characters, paired with the number of columns each occupies, along a row `width`
+/// columns wide starting at column `x`, as `print` draws them: `put(column, ch)` is called for
+/// each character that fits and for the blank that fills out each wide character, stopping at
+/// the first character that would run past the end of the row.  Zero-width characters are
+/// skipped.  Returns the number of columns written.
+pub fn layout_row<I, F>(x: usize, width: usize, chars: I, mut put: F) -> usize
+    where I: Iterator<Item=(char, usize)>, F: FnMut(usize, char)
+{
+    let mut written = 0;
+    for (ch, w) in chars {
+        if w == 0 {
+            continue;
+        }
+        if x >= width || written + w > width - x {
+            break;
+        }
+        put(x + written, ch);
+        for i in 1..w {
+            put(x + written + i, ' ');
+        }
+        written += w;
+    }
+    written
+}
+
+/// The number of terminal columns `print` would use to draw the given string on an unbounded
+/// screen.
+pub fn text_width(s: &str) -> usize {
+    s.chars().map(char_width).fold(0, |acc, w| acc + w)
+}
+
+/// The number of grapheme clusters (user-perceived characters, such as a letter followed by a
+/// combining accent, or an emoji sequence) in a string.  A text cursor should move by whole
+/// grapheme clusters.
+pub fn grapheme_count(s: &str) -> usize {
+    s.graphemes(true).count()
+}
+
+/// The column, relative to the start of the string, at which `print` draws the `n`th grapheme
+/// cluster (counting from 0), e.g. to place the cursor in an input field.  For `n` at or past
+/// the end of the string, this is the column just after it.
+pub fn nth_grapheme_column(s: &str, n: usize) -> usize {
+    s.graphemes(true).take(n).map(text_width).fold(0, |acc, w| acc + w)
+}
+
+/// Split a string into lines no wider than `width` columns.  Lines are broken at whitespace where
+/// possible (the whitespace itself is dropped) and words that don't fit on a line of their own
+/// are broken wherever they run out of room.  Embedded newlines always start a new line.
+pub fn wrap(s: &str, width: usize) -> Vec<&str> {
+    let mut lines = Vec::new();
+    if width == 0 {
+        return lines;
+    }
+    for paragraph in s.split('\n') {
+        let mut rest = paragraph;
+        loop {
+            if text_width(rest) <= width {
+                lines.push(rest);
+                break;
+            }
+
+            // Find the first character that doesn't fit, and the last whitespace before it.
+            let mut col = 0;
+            let mut end = rest.len();
+            let mut last_space = None;
+            for (i, ch) in rest.char_indices() {
+                let w = char_width(ch);
+                if col + w > width {
+                    end = i;
+                    break;
+                }
+                if ch.is_whitespace() {
+                    last_space = Some(i);
+                }
+                col += w;
+            }
+            if end == 0 {
+                // A single character wider than the line; give it a line of its own.
+                end = rest.chars().next().map(|c| c.len_utf8()).unwrap_or(0);
+            }
+
+            let split = match rest[end..].chars().next() {
+                Some(c) if c.is_whitespace() => end,
+                _ => match last_space {
+                    Some(i) if i > 0 => i,
+                    _ => end,
+                },
+            };
+            lines.push(rest[..split].trim_end());
+            rest = rest[split..].trim_start();
+            if rest.is_empty() {
+                break;
+            }
+        }
+    }
+    lines
+}
+
+/// A string whose characters and their widths have been measured up front, for text that is
+/// redrawn often (e.g. a status line drawn every frame).  Draw it with `RustBox::print_prepared`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PreparedText {
+    chars: Vec<(char, u16)>,
+    width: usize,
+}
+
+impl PreparedText {
+    pub fn new(s: &str) -> PreparedText {
+        let chars: Vec<(char, u16)> = s.chars()
+            .map(|ch| (ch, char_width(ch) as u16))
+            .filter(|&(_, w)| w > 0)
+            .collect();
+        let width = chars.iter().fold(0, |acc, &(_, w)| acc + w as usize);
+        PreparedText { chars, width }
+    }
+
+    /// The characters to draw along with the number of columns each occupies.  Characters that
+    /// occupy no columns have already been dropped.
+    pub fn chars(&self) -> &[(char, u16)] {
+        &self.chars
+    }
+
+    /// The total number of columns the text occupies.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{char_width, layout_row, wrap};
+
+    #[test]
+    fn wraps_at_whitespace() {
+        assert_eq!(wrap("hello world", 5), vec!["hello", "world"]);
+        assert_eq!(wrap("one two three", 7), vec!["one two", "three"]);
+        assert_eq!(wrap("ab cdefgh", 4), vec!["ab", "cdef", "gh"]);
+        assert_eq!(wrap("short", 10), vec!["short"]);
+    }
+
+    #[test]
+    fn breaks_long_words() {
+        assert_eq!(wrap("abcdefgh", 3), vec!["abc", "def", "gh"]);
+    }
+
+    #[test]
+    fn wide_characters() {
+        assert_eq!(wrap("日本語", 4), vec!["日本", "語"]);
+        assert_eq!(wrap("日本語", 3), vec!["日", "本", "語"]);
+        // A character wider than the line still gets a line of its own.
+        assert_eq!(wrap("日本", 1), vec!["日", "本"]);
+    }
+
+    #[test]
+    fn embedded_newlines() {
+        assert_eq!(wrap("a\nb", 10), vec!["a", "b"]);
+        assert_eq!(wrap("a\n\nb", 10), vec!["a", "", "b"]);
+        assert_eq!(wrap("abcd\nef", 2), vec!["ab", "cd", "ef"]);
+    }
+
+    fn layout(x: usize, width: usize, s: &str) -> (usize, Vec<(usize, char)>) {
+        let mut cells = Vec::new();
+        let written = layout_row(x, width, s.chars().map(|ch| (ch, char_width(ch))),
+                                 |col, ch| cells.push((col, ch)));
+        (written, cells)
+    }
+
+    #[test]
+    fn layout_clips_at_the_edge() {
+        assert_eq!(layout(1, 4, "abcd"), (3, vec![(1, 'a'), (2, 'b'), (3, 'c')]));
+        assert_eq!(layout(4, 4, "a"), (0, vec![]));
+        assert_eq!(layout(9, 4, "a"), (0, vec![]));
+    }
+
+    #[test]
+    fn layout_pads_wide_characters() {
+        assert_eq!(layout(0, 4, "a日"), (3, vec![(0, 'a'), (1, '日'), (2, ' ')]));
+        // A wide character that would straddle the edge isn't drawn at all.
+        assert_eq!(layout(0, 3, "ab日"), (2, vec![(0, 'a'), (1, 'b')]));
+        assert_eq!(layout(0, 4, "a\u{301}b"), (2, vec![(0, 'a'), (1, 'b')]));
+    }
+
+    #[test]
+    fn zero_width() {
+        assert!(wrap("anything", 0).is_empty());
+    }
+}
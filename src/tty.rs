@@ -0,0 +1,98 @@
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::PathBuf;
+use libc::{self, c_char, c_int};
+use termbox;
+
+// Entry points of the termbox library that termbox-sys doesn't declare.
+extern "C" {
+    fn tb_init_file(name: *const c_char) -> c_int;
+    fn tb_init_fd(inout: c_int) -> c_int;
+}
+
+/// The terminal termbox runs on.
+#[derive(Clone, Debug)]
+pub enum Target {
+    /// The controlling terminal, `/dev/tty`.
+    Default,
+    /// The terminal device at this path.
+    File(PathBuf),
+    /// An open terminal device.  The file descriptor belongs to the caller.
+    Fd(RawFd),
+}
+
+impl Target {
+    /// Initialize termbox on this terminal, returning termbox's result code.
+    pub unsafe fn init(&self) -> c_int {
+        match *self {
+            Target::Default => termbox::tb_init(),
+            Target::File(ref path) => match CString::new(path.as_os_str().as_bytes()) {
+                Ok(name) => tb_init_file(name.as_ptr()),
+                Err(_) => termbox::TB_EFAILED_TO_OPEN_TTY,
+            },
+            // termbox closes the descriptor it is given on shutdown, so give it a copy.
+            Target::Fd(fd) => match libc::dup(fd) {
+                -1 => termbox::TB_EFAILED_TO_OPEN_TTY,
+                copy => tb_init_fd(copy),
+            },
+        }
+    }
+
+    /// Open the terminal for writing escape sequences termbox doesn't know about.
+    pub fn open(&self) -> io::Result<Tty> {
+        let file = match *self {
+            Target::Default => try!(OpenOptions::new().write(true).open("/dev/tty")),
+            Target::File(ref path) => try!(OpenOptions::new().write(true).open(path)),
+            Target::Fd(fd) => match unsafe { libc::dup(fd) } {
+                -1 => return Err(io::Error::last_os_error()),
+                copy => unsafe { File::from_raw_fd(copy) },
+            },
+        };
+        Ok(Tty { file, queued: Vec::new() })
+    }
+}
+
+/// A handle on the controlling terminal, the same one termbox draws to.  Escape sequences termbox
+/// doesn't know about are queued here and written out after termbox's own output.
+pub struct Tty {
+    file: File,
+    queued: Vec<u8>,
+}
+
+impl Tty {
+    /// Write `bytes` straight away, ahead of anything queued.
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.file.write_all(bytes)
+    }
+
+    pub fn queue(&mut self, bytes: &[u8]) {
+        self.queued.extend_from_slice(bytes);
+    }
+
+    /// Write out everything queued.  Queued output is dropped even if writing fails.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let res = self.file.write_all(&self.queued);
+        self.queued.clear();
+        res
+    }
+
+    /// Check that the terminal is still connected.  Once it has been hung up (e.g. the SSH
+    /// connection dropped), anything termbox writes to it is silently lost.
+    pub fn check(&self) -> io::Result<()> {
+        let mut fds = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLOUT,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut fds, 1, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if fds.revents & (libc::POLLHUP | libc::POLLERR | libc::POLLNVAL) != 0 {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "the terminal has been hung up"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+//! Minimal immediate-mode widgets, drawn onto any `Surface`.
+//!
+//! The widgets keep no state of their own: redraw them every frame, passing in the event that
+//! arrived since the last one (if any), and they report what that event did to them.
+//!
+//! ```no_run
+//! use rustbox::{RustBox, Color, RB_NORMAL, RB_REVERSE};
+//! use rustbox::widgets::{button, label, text_input};
+//!
+//! let mut rb = RustBox::init().unwrap();
+//! let mut name = String::new();
+//! let mut ev = None;
+//! loop {
+//!     rb.clear();
+//!     label(&mut rb, 0, 0, 6, RB_NORMAL, Color::Default, Color::Default, "Name:");
+//!     text_input(&mut rb, 6, 0, 20, RB_REVERSE, Color::Default, Color::Default, &mut name,
+//!                ev.as_ref());
+//!     if button(&mut rb, 0, 2, RB_NORMAL, Color::Black, Color::White, "OK", ev.as_ref()) {
+//!         break;
+//!     }
+//!     rb.present();
+//!     ev = rb.poll_event().ok();
+//! }
+//! ```
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::{key, Color, Event, Style, Surface};
+use text::{char_width, text_width};
+
+/// Draw `text` at (x, y) in a field `width` columns wide: longer text is clipped and shorter
+/// text padded with blanks, so a label redrawn with shorter text leaves nothing behind.
+pub fn label<S: Surface>(s: &mut S, x: usize, y: usize, width: usize, sty: Style, fg: Color,
+                         bg: Color, text: &str) {
+    let mut fit = text.len();
+    let mut col = 0;
+    for (i, ch) in text.char_indices() {
+        col += char_width(ch);
+        if col > width {
+            fit = i;
+            break;
+        }
+    }
+    let written = s.print(x, y, sty, fg, bg, &text[..fit]);
+    for col in x + written..x.saturating_add(width).min(s.width()) {
+        s.print_char(col, y, sty, fg, bg, ' ');
+    }
+}
+
+/// Draw a button reading `[ text ]` at (x, y).  Returns whether `event` is a left click on it.
+pub fn button<S: Surface>(s: &mut S, x: usize, y: usize, sty: Style, fg: Color, bg: Color,
+                          text: &str, event: Option<&Event>) -> bool {
+    let width = s.print(x, y, sty, fg, bg, &format!("[ {} ]", text));
+    match event.and_then(|ev| if ev.is_left_click() { ev.mouse_position() } else { None }) {
+        Some((mx, my)) => {
+            mx >= 0 && my >= 0 && my as usize == y &&
+                mx as usize >= x && (mx as usize) < x + width
+        },
+        None => false,
+    }
+}
+
+/// Draw a single-line text field `width` columns wide at (x, y) showing `text`, and move the
+/// cursor to its end.  If `event` is a printable key, a paste or a backspace, `text` is edited
+/// accordingly first.  Returns whether `text` changed.
+///
+/// Text too long for the field is scrolled so that its end (and the cursor) stay in view.
+pub fn text_input<S: Surface>(s: &mut S, x: usize, y: usize, width: usize, sty: Style,
+                              fg: Color, bg: Color, text: &mut String,
+                              event: Option<&Event>) -> bool {
+    let changed = event.map_or(false, |ev| edit(text, ev));
+    if width == 0 {
+        return changed;
+    }
+
+    // Show as many whole graphemes from the end as fit, leaving a column for the cursor.
+    let mut start = text.len();
+    let mut shown = 0;
+    for (i, g) in text.grapheme_indices(true).rev() {
+        let w = text_width(g);
+        if shown + w > width - 1 {
+            break;
+        }
+        shown += w;
+        start = i;
+    }
+    label(s, x, y, width, sty, fg, bg, &text[start..]);
+    s.set_cursor(x + shown, y);
+    changed
+}
+
+// Apply a key press or paste to the contents of a text field.
+fn edit(text: &mut String, event: &Event) -> bool {
+    match *event {
+        Event::KeyEvent(None, k) => {
+            if let Some(ch) = k.as_char() {
+                if k.is_printable() {
+                    text.push(ch);
+                    return true;
+                }
+            }
+            if k == key::BACKSPACE || k == key::BACKSPACE2 {
+                let last = text.grapheme_indices(true).next_back().map(|(i, _)| i);
+                if let Some(i) = last {
+                    text.truncate(i);
+                    return true;
+                }
+            }
+            false
+        },
+        Event::Paste(ref pasted) => {
+            let before = text.len();
+            text.extend(pasted.chars().filter(|c| !c.is_control()));
+            text.len() != before
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::edit;
+    use {key, Event, Key, Modifier};
+
+    #[test]
+    fn typing_and_backspace() {
+        let mut text = String::new();
+        assert!(edit(&mut text, &Event::KeyEvent(None, Key::Char('h'))));
+        assert!(edit(&mut text, &Event::KeyEvent(None, key::SPACE)));
+        assert!(edit(&mut text, &Event::KeyEvent(None, Key::Char('é'))));
+        assert_eq!(text, "h é");
+        assert!(edit(&mut text, &Event::KeyEvent(None, key::BACKSPACE2)));
+        assert_eq!(text, "h ");
+        assert!(!edit(&mut text, &Event::KeyEvent(None, key::ENTER)));
+        assert!(!edit(&mut text, &Event::KeyEvent(Some(Modifier::Alt), Key::Char('x'))));
+        assert_eq!(text, "h ");
+    }
+
+    #[test]
+    fn backspace_removes_grapheme() {
+        let mut text = String::from("ae\u{301}");
+        assert!(edit(&mut text, &Event::KeyEvent(None, key::BACKSPACE)));
+        assert_eq!(text, "a");
+        assert!(edit(&mut text, &Event::KeyEvent(None, key::BACKSPACE)));
+        assert!(!edit(&mut text, &Event::KeyEvent(None, key::BACKSPACE)));
+    }
+
+    #[test]
+    fn paste_drops_control_characters() {
+        let mut text = String::from(">");
+        assert!(edit(&mut text, &Event::Paste(String::from("one\ntwo"))));
+        assert_eq!(text, ">onetwo");
+        assert!(!edit(&mut text, &Event::Paste(String::from("\n"))));
+    }
+}